@@ -1,8 +1,181 @@
 // build.rs
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+/// ABIs libffi may define, depending on the target. The ones `ffi.h`
+/// declares end up in `FFI_ABIS`.
+const ABI_CANDIDATES: &[&str] = &[
+    "FFI_SYSV",
+    "FFI_UNIX64",
+    "FFI_WIN64",
+    "FFI_EFI64",
+    "FFI_GNUW64",
+    "FFI_STDCALL",
+    "FFI_THISCALL",
+    "FFI_FASTCALL",
+    "FFI_MS_CDECL",
+    "FFI_PASCAL",
+    "FFI_REGISTER",
+    "FFI_VFP",
+    "FFI_LINUX",
+    "FFI_LINUX64",
+    "FFI_O32",
+    "FFI_N32",
+    "FFI_N64",
+];
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
-    pkg_config::Config::new()
+    let libffi = pkg_config::Config::new()
         .probe("libffi")
         .expect("Failed to find libffi via pkg-config");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let consts = ffi_consts(&out_dir, &libffi.include_paths);
+    fs::write(out_dir.join("ffi_consts.rs"), consts).unwrap();
+}
+
+/// The C compiler for the target, found the way the `cc` crate does.
+fn compiler() -> String {
+    let target = env::var("TARGET").unwrap_or_default();
+    [
+        format!("CC_{target}"),
+        format!("CC_{}", target.replace('-', "_")),
+        "TARGET_CC".to_string(),
+        "CC".to_string(),
+    ]
+    .iter()
+    .find_map(|var| {
+        println!("cargo:rerun-if-env-changed={var}");
+        env::var(var).ok()
+    })
+    .unwrap_or_else(|| "cc".to_string())
+}
+
+fn cc(includes: &[PathBuf]) -> Command {
+    let mut cmd = Command::new(compiler());
+    for dir in includes {
+        cmd.arg("-I").arg(dir);
+    }
+    cmd
+}
+
+/// Starts compiling a use of the ABI `name`, which succeeds when the
+/// target's `ffi.h` declares it.
+fn probe_abi(out_dir: &Path, includes: &[PathBuf], name: &str) -> Child {
+    let probe = out_dir.join(format!("abi_{name}.c"));
+    fs::write(&probe, format!("#include <ffi.h>\nint probe = {name};\n")).unwrap();
+    cc(includes)
+        .arg("-fsyntax-only")
+        .arg(&probe)
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run the C compiler")
+}
+
+/// Marks the values in the probe object, see `ffi_consts`.
+const MARKER: &str = "CREPLRS_FFI{";
+
+/// Reads the ABI and status constants and the `ffi_cif` layout of the
+/// target's `ffi.h`, and renders them as Rust. Nothing built for the target
+/// is run: the values are spelled out in decimal by constant expressions
+/// initialising a string, which is read back from the compiled object.
+fn ffi_consts(out_dir: &Path, includes: &[PathBuf]) -> String {
+    let probes: Vec<_> = ABI_CANDIDATES
+        .iter()
+        .map(|name| (*name, probe_abi(out_dir, includes, name)))
+        .collect();
+    let abis: Vec<&str> = probes
+        .into_iter()
+        .filter_map(|(name, mut probe)| {
+            let status = probe.wait().expect("Failed to run the C compiler");
+            status.success().then_some(name)
+        })
+        .collect();
+
+    let names: Vec<(&str, &str)> = [
+        ("FFI_CIF_SIZE", "sizeof(ffi_cif)"),
+        ("FFI_CIF_ALIGN", "_Alignof(ffi_cif)"),
+        ("FFI_DEFAULT_ABI", "FFI_DEFAULT_ABI"),
+        ("FFI_FIRST_ABI", "FFI_FIRST_ABI"),
+        ("FFI_LAST_ABI", "FFI_LAST_ABI"),
+        ("FFI_OK", "FFI_OK"),
+        ("FFI_BAD_TYPEDEF", "FFI_BAD_TYPEDEF"),
+        ("FFI_BAD_ABI", "FFI_BAD_ABI"),
+    ]
+    .into_iter()
+    .chain(abis.iter().map(|name| (*name, *name)))
+    .collect();
+    // a sign and 19 digits, enough for any `long long`
+    let mut src = "#include <ffi.h>\n\
+         #define MAG(v) ((v) < 0 ? 0ULL - (unsigned long long)(v) : (unsigned long long)(v))\n\
+         #define DIGIT(v, p) (char)('0' + MAG(v) / p##ULL % 10)\n\
+         #define NUM(v) (v) < 0 ? '-' : '+'"
+        .to_string();
+    for p in 0..19 {
+        src.push_str(&format!(", DIGIT(v, 1{})", "0".repeat(18 - p)));
+    }
+    src.push_str("\nconst char crepl_ffi_probe[] = {");
+    for c in MARKER.chars() {
+        src.push_str(&format!("'{c}', "));
+    }
+    for (name, expr) in &names {
+        src.push_str(&format!("\n    /* {name} */ NUM({expr}), ' ',"));
+    }
+    src.push_str("\n    '}'\n};\n");
+    let probe = out_dir.join("ffi_probe.c");
+    let object = out_dir.join("ffi_probe.o");
+    fs::write(&probe, src).unwrap();
+    let status = cc(includes)
+        .arg("-c")
+        .arg(&probe)
+        .arg("-o")
+        .arg(&object)
+        .status()
+        .expect("Failed to run the C compiler");
+    assert!(status.success(), "Failed to compile {}", probe.display());
+    let bytes = fs::read(&object).unwrap();
+    let start = bytes
+        .windows(MARKER.len())
+        .position(|w| w == MARKER.as_bytes())
+        .unwrap_or_else(|| panic!("No libffi values in {}", object.display()))
+        + MARKER.len();
+    let end = start + bytes[start..].iter().position(|b| *b == b'}').unwrap();
+    let numbers: Vec<i64> = std::str::from_utf8(&bytes[start..end])
+        .unwrap()
+        .split_whitespace()
+        .map(|n| n.parse().unwrap())
+        .collect();
+    assert_eq!(numbers.len(), names.len(), "Garbled libffi values");
+    let value = |name: &str| {
+        let i = names.iter().position(|(n, _)| *n == name).unwrap();
+        numbers[i]
+    };
+
+    let mut out = format!(
+        "// Generated by build.rs from the target's ffi.h\n\n\
+         /// `ffi_cif`, only ever handled through pointers by libffi.\n\
+         #[repr(C, align({}))]\n\
+         pub struct ffi_cif {{\n    _opaque: [u8; {}],\n}}\n\n",
+        value("FFI_CIF_ALIGN"),
+        value("FFI_CIF_SIZE")
+    );
+    for name in ["FFI_DEFAULT_ABI", "FFI_FIRST_ABI", "FFI_LAST_ABI"] {
+        out.push_str(&format!("pub const {name}: ABI = {};\n", value(name)));
+    }
+    for name in ["FFI_OK", "FFI_BAD_TYPEDEF", "FFI_BAD_ABI"] {
+        out.push_str(&format!("pub const {name}: Status = {};\n", value(name)));
+    }
+    out.push_str(
+        "\n/// The ABIs of this target, by name.\npub const FFI_ABIS: &[(&str, ABI)] = &[\n",
+    );
+    for name in &abis {
+        out.push_str(&format!("    (\"{name}\", {}),\n", value(name)));
+    }
+    out.push_str("];\n");
+    out
 }
@@ -0,0 +1,227 @@
+use std::{
+    ffi::{c_char, c_void},
+    hint::black_box,
+    time::Instant,
+};
+
+use crate::{
+    call::{Prepared, arg_value, prepare, render_call},
+    cffi::{CifCache, FfiError, FfiType},
+    error::Error,
+    lex::Token,
+    proto::CType,
+    session::Session,
+};
+
+const USAGE: &str = ":bench [-n N] [--warmup W] <call>";
+
+/// How long one call took.
+struct Sample {
+    ns: u64,
+    /// Time stamp counter ticks, where there is one
+    cycles: Option<u64>,
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cycles() -> Option<u64> {
+    Some(unsafe { std::arch::x86_64::_rdtsc() })
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cycles() -> Option<u64> {
+    None
+}
+
+/// Makes `warmup` untimed calls, then `iterations` timed ones, all through
+/// the same `CallInterface` and argument buffers.
+fn sample<R>(
+    cifs: &mut CifCache,
+    prepared: &mut Prepared,
+    iterations: usize,
+    warmup: usize,
+) -> Result<Vec<Sample>, FfiError>
+where
+    R: Into<FfiType> + Default + 'static,
+{
+    let cif = cifs.get::<R>(prepared.abi, &prepared.arg_types)?;
+    let f = prepared.func.addr() as *mut c_void;
+    let args = prepared.arena.args();
+    for _ in 0..warmup {
+        black_box(cif.call(f, args));
+    }
+    Ok((0..iterations)
+        .map(|_| {
+            let start_cycles = cycles();
+            let start = Instant::now();
+            black_box(cif.call(f, args));
+            let ns = start.elapsed().as_nanos() as u64;
+            let cycles = cycles()
+                .zip(start_cycles)
+                .map(|(end, start)| end.wrapping_sub(start));
+            Sample { ns, cycles }
+        })
+        .collect())
+}
+
+fn sample_as(
+    cifs: &mut CifCache,
+    prepared: &mut Prepared,
+    iterations: usize,
+    warmup: usize,
+) -> Result<Vec<Sample>, FfiError> {
+    match prepared.ret {
+        CType::Void => sample::<()>(cifs, prepared, iterations, warmup),
+        CType::Char => sample::<i8>(cifs, prepared, iterations, warmup),
+        CType::UChar => sample::<u8>(cifs, prepared, iterations, warmup),
+        CType::Short => sample::<i16>(cifs, prepared, iterations, warmup),
+        CType::UShort => sample::<u16>(cifs, prepared, iterations, warmup),
+        CType::Int => sample::<i32>(cifs, prepared, iterations, warmup),
+        CType::UInt => sample::<u32>(cifs, prepared, iterations, warmup),
+        CType::Long => sample::<i64>(cifs, prepared, iterations, warmup),
+        CType::ULong => sample::<u64>(cifs, prepared, iterations, warmup),
+        CType::Float => sample::<f32>(cifs, prepared, iterations, warmup),
+        CType::Double => sample::<f64>(cifs, prepared, iterations, warmup),
+        CType::String => sample::<*const c_char>(cifs, prepared, iterations, warmup),
+        CType::Pointer => sample::<*mut c_void>(cifs, prepared, iterations, warmup),
+    }
+}
+
+/// Summary of a series of measurements.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub min: u64,
+    pub median: u64,
+    pub mean: f64,
+    pub p99: u64,
+    pub stddev: f64,
+}
+
+impl Stats {
+    fn of(mut values: Vec<u64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        let n = values.len();
+        let mean = values.iter().sum::<u64>() as f64 / n as f64;
+        let variance = values
+            .iter()
+            .map(|v| (*v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n as f64;
+        Some(Self {
+            min: values[0],
+            // the two middle values averaged, rounded down, for an even count
+            median: match n % 2 {
+                0 => values[n / 2 - 1].midpoint(values[n / 2]),
+                _ => values[n / 2],
+            },
+            mean,
+            p99: values[(n * 99 / 100).min(n - 1)],
+            stddev: variance.sqrt(),
+        })
+    }
+}
+
+/// Reads the `-n` and `--warmup` options, returning them with the index of
+/// the first token of the call.
+fn options(args: &[(Token, String)]) -> Result<(usize, usize, usize), Error> {
+    let mut iterations = 10_000;
+    let mut warmup = 100;
+    let mut i = 0;
+    loop {
+        let (option, value_at) = match args.get(i..) {
+            Some([(Token::Minus, _), (Token::Id, n), ..]) if n == "n" => (&mut iterations, i + 2),
+            Some([(Token::Minus, _), (Token::Minus, _), (Token::Id, w), ..]) if w == "warmup" => {
+                (&mut warmup, i + 3)
+            }
+            _ => return Ok((iterations, warmup, i)),
+        };
+        *option = match args.get(value_at) {
+            Some((Token::CInt, n)) => n
+                .parse()
+                .map_err(|_| Error::parse(format!("`{n}` is not a count")).at(value_at))?,
+            _ => return Err(Error::parse(format!("expected Syntax is `{USAGE}`")).at(value_at)),
+        };
+        if iterations == 0 {
+            return Err(Error::eval("`-n` must be at least 1").at(value_at));
+        }
+        i = value_at + 1;
+    }
+}
+
+/// `:bench [-n N] [--warmup W] <call>`: times `N` calls after `W` warmup
+/// ones, FFI setup excluded.
+pub fn bench(args: &[(Token, String)], session: &mut Session) -> Result<(), Error> {
+    let (iterations, warmup, first) = options(args)?;
+    let call = &args[first..];
+    match call.first() {
+        Some((Token::Id | Token::SymRef, _)) => {}
+        _ => return Err(Error::parse(format!("expected Syntax is `{USAGE}`")).at(first)),
+    }
+    let values = call
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, tok)| arg_value(&session.env, tok).map_err(|e| e.at(first + i)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut prepared = prepare(session, &call[0].1, &values).map_err(|e| e.shifted(first))?;
+    if let Some(dtor) = prepared.proto.as_ref().and_then(|p| p.owned.clone()) {
+        session.reuse_arena(prepared.arena);
+        return Err(Error::eval(format!(
+            "`{}` is declared owned({dtor}), every call benchmarked would leak what it returns",
+            call[0].1
+        ))
+        .at(first));
+    }
+    let samples = sample_as(&mut session.cifs, &mut prepared, iterations, warmup);
+    session.reuse_arena(prepared.arena);
+    let samples = samples?;
+
+    println!(
+        "INFO: `{}`, {iterations} calls after {warmup} warmup ones",
+        render_call(call)
+    );
+    let ns = Stats::of(samples.iter().map(|s| s.ns).collect()).unwrap();
+    println!("\tmin     {:>10} ns", ns.min);
+    println!("\tmedian  {:>10} ns", ns.median);
+    println!("\tmean    {:>10.1} ns", ns.mean);
+    println!("\tp99     {:>10} ns", ns.p99);
+    println!("\tstddev  {:>10.1} ns", ns.stddev);
+    let cycles: Option<Vec<u64>> = samples.iter().map(|s| s.cycles).collect();
+    if let Some(cycles) = cycles.and_then(Stats::of) {
+        println!(
+            "\tcycles  {:>10} median, {:.1} mean, {} min",
+            cycles.median, cycles.mean, cycles.min
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarises_measurements() {
+        assert!(Stats::of(Vec::new()).is_none());
+
+        let one = Stats::of(vec![7]).unwrap();
+        assert_eq!((one.min, one.median, one.p99), (7, 7, 7));
+        assert_eq!((one.mean, one.stddev), (7.0, 0.0));
+
+        let stats = Stats::of(vec![4, 2, 8, 6]).unwrap();
+        assert_eq!((stats.min, stats.median, stats.p99), (2, 5, 8));
+        assert_eq!(stats.mean, 5.0);
+        assert!((stats.stddev - 5f64.sqrt()).abs() < 1e-9);
+
+        // the slowest percent is left out of p99
+        let mut values: Vec<u64> = (1..=1000).collect();
+        values.reverse();
+        let stats = Stats::of(values).unwrap();
+        assert_eq!((stats.min, stats.median, stats.p99), (1, 500, 991));
+
+        let stats = Stats::of(vec![9, 1, 4]).unwrap();
+        assert_eq!(stats.median, 4);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    ffi::{c_char, c_void},
+    mem,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cffi::{ABI, ArgArena, CifCache, FfiError, FfiType},
+    dlfcn::DlSym,
+    error::Error,
+    eval::{Env, HistoryEntry, Value},
+    heap,
+    lex::Token,
+    parser::parse_int,
+    proto::{CType, Prototype},
+    registry::base_name,
+    session::Session,
+    trace::{CallTrace, TraceArg},
+};
+
+/// What a call returned.
+#[derive(Debug, Clone)]
+pub struct CallOutput {
+    /// `$n` the result was recorded as, `None` for `void`
+    pub index: Option<usize>,
+    pub ty: CType,
+    pub value: Option<Value>,
+    /// `errno` after the call, when the prototype says the function sets it
+    pub errno: Option<i32>,
+}
+
+/// Calls the function named by the first token with the others as arguments.
+pub fn call(session: &mut Session, tokens: &[(Token, String)]) -> Result<CallOutput, Error> {
+    if tokens[0].0 != Token::Id && tokens[0].0 != Token::SymRef {
+        return Err(Error::parse("Expected a function as the first lexeme").at(0));
+    }
+    let proto = session.protos.get(base_name(&tokens[0].1)).cloned();
+    // a variable named at an `owned` out-param receives the pointer, the
+    // function writing it to a slot of ours
+    let mut outs: Vec<(&str, &str, Box<usize>)> = Vec::new();
+    let mut values = Vec::with_capacity(tokens.len() - 1);
+    for (i, tok) in tokens.iter().enumerate().skip(1) {
+        let dtor = proto.as_ref().and_then(|p| p.owned_arg(i - 1));
+        match (dtor, tok) {
+            (Some(dtor), (Token::Id, name)) => {
+                let slot = Box::new(0usize);
+                values.push(Value::Pointer(&*slot as *const usize as usize));
+                outs.push((name, dtor, slot));
+            }
+            _ => values.push(arg_value(&session.env, tok).map_err(|e| e.at(i))?),
+        }
+    }
+    let call_text = render_call(tokens);
+    let out = invoke(session, &tokens[0].1, &values, call_text.clone())?;
+    for (name, dtor, slot) in outs {
+        if *slot != 0 {
+            session.allocs.track(*slot, dtor, &call_text);
+        }
+        session
+            .env
+            .set_var(name.to_string(), Value::Pointer(*slot))?;
+    }
+    Ok(out)
+}
+
+/// A call with its arguments marshalled, ready to be made, once or many
+/// times.
+pub struct Prepared {
+    /// The library `func` was found in
+    pub lib: String,
+    pub func: DlSym,
+    pub proto: Option<Prototype>,
+    pub ret: CType,
+    /// Calling convention, see `:abi`
+    pub abi: ABI,
+    pub arg_types: Vec<FfiType>,
+    /// The marshalled arguments, see `ArgArena::args`
+    pub arena: ArgArena,
+}
+
+/// Looks `sym` up and marshals `values` for it, typed by its prototype or
+/// else by the mode, into the arena of `session`, hand it back with
+/// `Session::reuse_arena` once done. Errors blame the tokens of the call,
+/// `sym` being the first and `values` the following ones.
+pub fn prepare(session: &mut Session, sym: &str, values: &[Value]) -> Result<Prepared, Error> {
+    let (lib, func) = session.libs.resolve(sym).map_err(|e| e.at(0))?;
+    let proto = session.protos.get(base_name(sym)).cloned();
+    if let Some(proto) = &proto {
+        let arity_ok = if proto.variadic {
+            values.len() >= proto.args.len()
+        } else {
+            values.len() == proto.args.len()
+        };
+        if !arity_ok {
+            return Err(Error::eval(format!(
+                "`{}` expects {} argument(s), got {}",
+                proto.display(sym),
+                proto.args.len(),
+                values.len()
+            ))
+            .at(0));
+        }
+    }
+
+    let mut arg_types = Vec::with_capacity(values.len());
+    let mut arena = mem::take(&mut session.arena);
+    arena.clear();
+    for (i, value) in values.iter().enumerate() {
+        let ty = proto
+            .as_ref()
+            .and_then(|p| p.args.get(i))
+            .map(|t| t.ffi_type())
+            .unwrap_or_else(|| default_ffi_type(value));
+        push_arg(value, ty, &mut arena)
+            .map_err(|e| Error::new(e.kind, format!("argument {}: {}", i + 1, e.msg)).at(i + 1))?;
+        arg_types.push(ty);
+    }
+    let ret = proto
+        .as_ref()
+        .map(|p| p.ret)
+        .unwrap_or_else(|| session.mode.ret_type());
+    Ok(Prepared {
+        lib: lib.to_string(),
+        func,
+        proto,
+        ret,
+        abi: session.abi,
+        arg_types,
+        arena,
+    })
+}
+
+/// Calls `sym` with `values`, see `prepare`, and records the result in the
+/// history as `call_text`.
+pub fn invoke(
+    session: &mut Session,
+    sym: &str,
+    values: &[Value],
+    call_text: String,
+) -> Result<CallOutput, Error> {
+    let mut prepared = prepare(session, sym, values)?;
+    let ret = prepared.ret;
+    let args = prepared.arena.args();
+    let (value, returned) = call_as(
+        &mut session.cifs,
+        prepared.abi,
+        ret,
+        prepared.func.addr() as *mut c_void,
+        &prepared.arg_types,
+        args,
+    )?;
+    let errno = returned.errno;
+    // built while the arena still holds the arguments, recorded once the
+    // call is fully accounted for
+    let trace = session.trace.enabled.then(|| {
+        let args = values
+            .iter()
+            .zip(&prepared.arg_types)
+            .zip(args)
+            .map(|((value, ty), addr)| TraceArg {
+                ty: *ty,
+                value: value.clone(),
+                addr: *addr as usize,
+                ptr: matches!(value, Value::CString(_))
+                    .then(|| unsafe { *(*addr as *const usize) }),
+            })
+            .collect();
+        CallTrace {
+            lib: prepared.lib.clone(),
+            sym: sym.to_string(),
+            addr: prepared.func.addr(),
+            args,
+            ret,
+            value: value.clone(),
+            raw: returned.raw,
+            errno,
+            elapsed: returned.elapsed,
+        }
+    });
+    session.reuse_arena(prepared.arena);
+    session.env.set_errno(errno);
+    if let Some(heap_call) = returned.heap_call {
+        session.heap.record_call(heap_call, &call_text);
+        let misuses = session.heap.misuses();
+        session.warnings.extend(misuses);
+    }
+    if let (Some(dtor), Some(Value::Pointer(addr @ 1..))) = (
+        prepared.proto.as_ref().and_then(|p| p.owned.as_ref()),
+        &value,
+    ) {
+        session.allocs.track(*addr, dtor, &call_text);
+    }
+    let index = value.as_ref().map(|value| {
+        session.env.push_history(HistoryEntry {
+            call: call_text,
+            ty: ret,
+            value: value.clone(),
+            dropped: false,
+        })
+    });
+    if let Some(trace) = trace
+        && let Err(e) = session.trace.record(trace)
+    {
+        session.warnings.push(e);
+    }
+    Ok(CallOutput {
+        index,
+        ty: ret,
+        value,
+        errno: prepared.proto.filter(|p| p.sets_errno).map(|_| errno),
+    })
+}
+
+/// The value a call argument token stands for.
+pub fn arg_value(env: &Env, token: &(Token, String)) -> Result<Value, Error> {
+    match token.0 {
+        Token::CString => Ok(Value::CString(token.1.clone())),
+        Token::CInt => parse_int(&token.1)
+            .map(Value::Integer)
+            .map_err(Error::parse),
+        Token::CFloat => token
+            .1
+            .parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| Error::parse(format!("Invalid float: {}", token.1))),
+        Token::CChar => Ok(Value::CChar(token.1.chars().next().unwrap())),
+        Token::HistRef => env
+            .get(&token.1)
+            .ok_or_else(|| Error::eval(format!("no result `{}`", token.1))),
+        Token::Id => env.get(&token.1).ok_or_else(|| {
+            Error::eval(format!("variable or constant `{}` does not exist", token.1))
+        }),
+        _ => Err(Error::parse(format!(
+            "`{}` can not be passed as an argument",
+            token.1
+        ))),
+    }
+}
+
+/// Type an argument is passed as when the function has no prototype.
+fn default_ffi_type(value: &Value) -> FfiType {
+    match value {
+        Value::CString(_) => FfiType::Pointer,
+        Value::CChar(_) | Value::Bool(_) => FfiType::SInt8,
+        Value::Integer(_) => FfiType::SInt64,
+        Value::Number(_) => FfiType::Double,
+        Value::Pointer(_) => FfiType::Pointer,
+    }
+}
+
+fn push_arg(value: &Value, ty: FfiType, arena: &mut ArgArena) -> Result<(), Error> {
+    let int = match value {
+        Value::Integer(i) => Some(*i),
+        Value::Pointer(p) => Some(*p as i64),
+        Value::CChar(c) => Some(*c as i64),
+        Value::Bool(b) => Some(*b as i64),
+        _ => None,
+    };
+    let float = match value {
+        Value::Number(n) => Some(*n),
+        Value::Integer(i) => Some(*i as f64),
+        _ => None,
+    };
+    let mismatch = || Error::eval(format!("can not pass {value:?} as {ty:?}"));
+    match ty {
+        FfiType::Pointer => match value {
+            Value::CString(s) => arena
+                .push_str(s)
+                .map_err(|e| Error::memory(e.to_string()))?,
+            _ => arena.push(int.ok_or_else(mismatch)? as usize),
+        },
+        FfiType::SInt8 => arena.push(int.ok_or_else(mismatch)? as i8),
+        FfiType::UInt8 => arena.push(int.ok_or_else(mismatch)? as u8),
+        FfiType::SInt16 => arena.push(int.ok_or_else(mismatch)? as i16),
+        FfiType::UInt16 => arena.push(int.ok_or_else(mismatch)? as u16),
+        FfiType::SInt32 => arena.push(int.ok_or_else(mismatch)? as i32),
+        FfiType::UInt32 => arena.push(int.ok_or_else(mismatch)? as u32),
+        FfiType::SInt64 => arena.push(int.ok_or_else(mismatch)?),
+        FfiType::UInt64 => arena.push(int.ok_or_else(mismatch)? as u64),
+        FfiType::Float => arena.push(float.ok_or_else(mismatch)? as f32),
+        FfiType::Double => arena.push(float.ok_or_else(mismatch)?),
+        FfiType::Void => return Err(mismatch()),
+    }
+    Ok(())
+}
+
+/// What a call left behind besides its value.
+struct Returned {
+    /// The return register, before it is converted
+    raw: u64,
+    errno: i32,
+    elapsed: Duration,
+    /// Number the allocations of the call were recorded under, with
+    /// `--heapcheck`
+    heap_call: Option<u64>,
+}
+
+fn call_typed<R>(
+    cifs: &mut CifCache,
+    abi: ABI,
+    f: *mut c_void,
+    arg_types: &[FfiType],
+    args: &[*mut c_void],
+) -> Result<(R, Returned), FfiError>
+where
+    R: Into<FfiType> + Default + 'static,
+{
+    let cif = cifs.get::<R>(abi, arg_types)?;
+    let heap_call = heap::begin();
+    let start = Instant::now();
+    let res = cif.call(f, args);
+    let elapsed = start.elapsed();
+    heap::end();
+    Ok((
+        res,
+        Returned {
+            raw: cif.raw_return(),
+            errno: cif.errno(),
+            elapsed,
+            heap_call,
+        },
+    ))
+}
+
+/// Calls `f` returning `ret`, `None` for `void`.
+fn call_as(
+    cifs: &mut CifCache,
+    abi: ABI,
+    ret: CType,
+    f: *mut c_void,
+    arg_types: &[FfiType],
+    args: &[*mut c_void],
+) -> Result<(Option<Value>, Returned), FfiError> {
+    fn int<T: Into<i64>>((res, returned): (T, Returned)) -> (Option<Value>, Returned) {
+        (Some(Value::Integer(res.into())), returned)
+    }
+    Ok(match ret {
+        CType::Void => (None, call_typed::<()>(cifs, abi, f, arg_types, args)?.1),
+        CType::Char => {
+            let (res, returned) = call_typed::<i8>(cifs, abi, f, arg_types, args)?;
+            (Some(Value::CChar(res as u8 as char)), returned)
+        }
+        CType::UChar => int(call_typed::<u8>(cifs, abi, f, arg_types, args)?),
+        CType::Short => int(call_typed::<i16>(cifs, abi, f, arg_types, args)?),
+        CType::UShort => int(call_typed::<u16>(cifs, abi, f, arg_types, args)?),
+        CType::Int => int(call_typed::<i32>(cifs, abi, f, arg_types, args)?),
+        CType::UInt => int(call_typed::<u32>(cifs, abi, f, arg_types, args)?),
+        CType::Long => int(call_typed::<i64>(cifs, abi, f, arg_types, args)?),
+        CType::ULong => {
+            let (res, returned) = call_typed::<u64>(cifs, abi, f, arg_types, args)?;
+            int((res as i64, returned))
+        }
+        CType::Float => {
+            let (res, returned) = call_typed::<f32>(cifs, abi, f, arg_types, args)?;
+            (Some(Value::Number(res as f64)), returned)
+        }
+        CType::Double => {
+            let (res, returned) = call_typed::<f64>(cifs, abi, f, arg_types, args)?;
+            (Some(Value::Number(res)), returned)
+        }
+        // never dereferenced here, `show_result` and `:str` read them safely
+        CType::String => {
+            let (res, returned) = call_typed::<*const c_char>(cifs, abi, f, arg_types, args)?;
+            (Some(Value::Pointer(res as usize)), returned)
+        }
+        CType::Pointer => {
+            let (res, returned) = call_typed::<*mut c_void>(cifs, abi, f, arg_types, args)?;
+            (Some(Value::Pointer(res as usize)), returned)
+        }
+    })
+}
+
+/// The call as the user typed it, for `:hist`.
+pub fn render_call(tokens: &[(Token, String)]) -> String {
+    tokens
+        .iter()
+        .map(|(tok, text)| match tok {
+            Token::CString => format!("{text:?}"),
+            Token::CChar => format!("'{text}'"),
+            _ => text.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
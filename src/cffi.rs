@@ -1,4 +1,6 @@
 use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
     error::Error,
     ffi::c_void,
     fmt::{self, Display, Formatter},
@@ -9,9 +11,26 @@ use std::{
 pub type ABI = u32;
 pub type Status = i32;
 
-pub const FFI_UNIX64: ABI = 2;
-pub const FFI_DEFAULT_ABI: ABI = FFI_UNIX64;
-pub const FFI_OK: Status = 0;
+include!(concat!(env!("OUT_DIR"), "/ffi_consts.rs"));
+
+/// The ABI called `name`, as `FFI_EFI64` or just `efi64`.
+pub fn parse_abi(name: &str) -> Option<ABI> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("FFI_").unwrap_or(&name);
+    FFI_ABIS
+        .iter()
+        .find(|(n, _)| n.strip_prefix("FFI_") == Some(name))
+        .map(|(_, abi)| *abi)
+}
+
+/// The names of `abi`, several when libffi aliases it.
+pub fn abi_names(abi: ABI) -> Vec<&'static str> {
+    FFI_ABIS
+        .iter()
+        .filter(|(_, a)| *a == abi)
+        .map(|(name, _)| *name)
+        .collect()
+}
 
 #[repr(C)]
 pub struct ffi_type {
@@ -21,17 +40,6 @@ pub struct ffi_type {
     pub elements: *mut *mut ffi_type,
 }
 
-#[repr(C)]
-#[derive(Debug)]
-pub struct ffi_cif {
-    pub abi: ABI,
-    pub nargs: u32,
-    pub arg_types: *mut *mut ffi_type,
-    pub rtype: *mut ffi_type,
-    pub bytes: u32,
-    pub flags: u32,
-}
-
 #[link(name = "ffi")]
 unsafe extern "C" {
     // Common ffi_type constants (these would be defined in libffi)
@@ -65,7 +73,7 @@ unsafe extern "C" {
 }
 
 #[derive(Debug)]
-pub struct FfiError(String);
+pub struct FfiError(pub(crate) String);
 
 impl Display for FfiError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -82,7 +90,7 @@ impl FfiError {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FfiType {
     Void,
     SInt8,
@@ -100,21 +108,19 @@ pub enum FfiType {
 
 impl FfiType {
     pub fn raw(&self) -> *mut ffi_type {
-        unsafe {
-            match self {
-                FfiType::Void => &raw mut ffi_type_void as *mut _,
-                FfiType::SInt8 => &raw mut ffi_type_sint8 as *mut _,
-                FfiType::SInt16 => &raw mut ffi_type_sint16 as *mut _,
-                FfiType::SInt32 => &raw mut ffi_type_sint32 as *mut _,
-                FfiType::SInt64 => &raw mut ffi_type_sint64 as *mut _,
-                FfiType::UInt8 => &raw mut ffi_type_uint8 as *mut _,
-                FfiType::UInt16 => &raw mut ffi_type_uint16 as *mut _,
-                FfiType::UInt32 => &raw mut ffi_type_uint32 as *mut _,
-                FfiType::UInt64 => &raw mut ffi_type_uint64 as *mut _,
-                FfiType::Float => &raw mut ffi_type_float as *mut _,
-                FfiType::Double => &raw mut ffi_type_double as *mut _,
-                FfiType::Pointer => &raw mut ffi_type_pointer as *mut _,
-            }
+        match self {
+            FfiType::Void => &raw mut ffi_type_void as *mut _,
+            FfiType::SInt8 => &raw mut ffi_type_sint8 as *mut _,
+            FfiType::SInt16 => &raw mut ffi_type_sint16 as *mut _,
+            FfiType::SInt32 => &raw mut ffi_type_sint32 as *mut _,
+            FfiType::SInt64 => &raw mut ffi_type_sint64 as *mut _,
+            FfiType::UInt8 => &raw mut ffi_type_uint8 as *mut _,
+            FfiType::UInt16 => &raw mut ffi_type_uint16 as *mut _,
+            FfiType::UInt32 => &raw mut ffi_type_uint32 as *mut _,
+            FfiType::UInt64 => &raw mut ffi_type_uint64 as *mut _,
+            FfiType::Float => &raw mut ffi_type_float as *mut _,
+            FfiType::Double => &raw mut ffi_type_double as *mut _,
+            FfiType::Pointer => &raw mut ffi_type_pointer as *mut _,
         }
     }
 }
@@ -130,6 +136,18 @@ impl From<u8> for FfiType {
     }
 }
 
+impl From<i16> for FfiType {
+    fn from(_: i16) -> Self {
+        FfiType::SInt16
+    }
+}
+
+impl From<u16> for FfiType {
+    fn from(_: u16) -> Self {
+        FfiType::UInt16
+    }
+}
+
 impl From<i32> for FfiType {
     fn from(_: i32) -> Self {
         FfiType::SInt32
@@ -189,12 +207,110 @@ where
     R: Into<FfiType>,
 {
     cif: ffi_cif,
-    arg_types: Vec<FfiType>,
-    arg_types_raw: Vec<*mut ffi_type>,
+    arg_types: Box<[FfiType]>,
+    // `cif` points into it, a boxed slice never reallocates so the interface
+    // can be moved and kept around
+    #[allow(dead_code)]
+    arg_types_raw: Box<[*mut ffi_type]>,
     ret_type: FfiType,
+    errno: i32,
+    raw: u64,
     phantom: std::marker::PhantomData<R>,
 }
 
+/// `CallInterface`s already prepared, by ABI, return and argument types, so
+/// that calls with a signature seen before skip `ffi_prep_cif`.
+#[derive(Default)]
+pub struct CifCache {
+    cifs: HashMap<(ABI, TypeId), ByArgTypes>,
+}
+
+/// `CallInterface<R>`s, for one `R`, by argument types.
+type ByArgTypes = HashMap<Vec<FfiType>, Box<dyn Any>>;
+
+impl CifCache {
+    /// The interface for `abi` calls returning `R` with `arg_types`, prepared
+    /// on first use.
+    pub fn get<R>(
+        &mut self,
+        abi: ABI,
+        arg_types: &[FfiType],
+    ) -> Result<&mut CallInterface<R>, FfiError>
+    where
+        R: Into<FfiType> + Default + 'static,
+    {
+        let cifs = self.cifs.entry((abi, TypeId::of::<R>())).or_default();
+        if !cifs.contains_key(arg_types) {
+            let cif = CallInterface::<R>::with_abi(abi, arg_types.iter().copied())?;
+            cifs.insert(arg_types.to_vec(), Box::new(cif));
+        }
+        Ok(cifs
+            .get_mut(arg_types)
+            .and_then(|cif| cif.downcast_mut())
+            .expect("interfaces are cached under the TypeId of what they return"))
+    }
+}
+
+/// Storage for the arguments of a call, kept from one call to the next so
+/// that marshalling stops allocating once it has grown large enough.
+#[derive(Debug, Default)]
+pub struct ArgArena {
+    /// One slot per argument, every scalar and pointer fits in 8 bytes
+    slots: Vec<u64>,
+    /// The strings passed, each nul terminated
+    strings: Vec<u8>,
+    /// Slots passing a string, with the offset of the string in `strings`
+    string_slots: Vec<(usize, usize)>,
+    ptrs: Vec<*mut c_void>,
+}
+
+impl ArgArena {
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.strings.clear();
+        self.string_slots.clear();
+        self.ptrs.clear();
+    }
+
+    /// Adds an argument of a type no wider than 8 bytes.
+    pub fn push<T: Copy>(&mut self, value: T) {
+        assert!(mem::size_of::<T>() <= mem::size_of::<u64>());
+        let mut slot = 0u64;
+        unsafe { std::ptr::write(&mut slot as *mut u64 as *mut T, value) };
+        self.slots.push(slot);
+    }
+
+    /// Adds a `char*` argument pointing to a nul terminated copy of `s`.
+    pub fn push_str(&mut self, s: &str) -> Result<(), FfiError> {
+        if let Some(at) = s.find('\0') {
+            return Err(FfiError(format!(
+                "nul byte found in a string argument at position {at}"
+            )));
+        }
+        self.string_slots
+            .push((self.slots.len(), self.strings.len()));
+        self.strings.extend_from_slice(s.as_bytes());
+        self.strings.push(0);
+        self.slots.push(0);
+        Ok(())
+    }
+
+    /// Pointers to the arguments, as `ffi_call` takes them. They stay valid
+    /// until the arena is changed.
+    pub fn args(&mut self) -> &[*mut c_void] {
+        for &(slot, offset) in &self.string_slots {
+            self.slots[slot] = self.strings.as_ptr().wrapping_add(offset) as u64;
+        }
+        self.ptrs.clear();
+        self.ptrs.extend(
+            self.slots
+                .iter_mut()
+                .map(|slot| slot as *mut u64 as *mut c_void),
+        );
+        &self.ptrs
+    }
+}
+
 pub trait IntoFfiArg {
     fn into_ffi(self, boxes: &mut Vec<Box<dyn std::any::Any>>) -> *mut std::ffi::c_void;
 }
@@ -268,6 +384,14 @@ where
     R: Default,
 {
     pub fn new<A>(arg_types: A) -> Result<Self, FfiError>
+    where
+        A: IntoIterator<Item = FfiType>,
+    {
+        Self::with_abi(FFI_DEFAULT_ABI, arg_types)
+    }
+
+    /// Prepares calls following the calling convention `abi`, see `FFI_ABIS`.
+    pub fn with_abi<A>(abi: ABI, arg_types: A) -> Result<Self, FfiError>
     where
         A: IntoIterator<Item = FfiType>,
     {
@@ -279,7 +403,7 @@ where
         // println!("Argument types: {:?}", arg_types_vec);
         // println!("Number of arguments: {}", arg_types_vec.len());
 
-        let mut arg_types_raw_vec: Vec<*mut ffi_type> =
+        let mut arg_types_raw_vec: Box<[*mut ffi_type]> =
             arg_types_vec.iter().map(|t| t.raw()).collect();
         // println!("Raw argument pointers: {:?}", arg_types_raw_vec);
 
@@ -288,7 +412,7 @@ where
         let result: Status = unsafe {
             ffi_prep_cif(
                 &mut cif,
-                FFI_DEFAULT_ABI,
+                abi,
                 arg_types_raw_vec.len() as u32,
                 ret_type.raw(),
                 arg_types_raw_vec.as_mut_ptr(),
@@ -297,17 +421,23 @@ where
         // println!("ffi_prep_cif result: {}", result);
 
         if result != FFI_OK {
+            let status = match result {
+                FFI_BAD_ABI => "FFI_BAD_ABI",
+                FFI_BAD_TYPEDEF => "FFI_BAD_TYPEDEF",
+                _ => "an error",
+            };
             return Err(FfiError(format!(
-                "Error Preparing the CallInterface: C::ffi_prep_cif returned {}",
-                result
+                "Error Preparing the CallInterface: C::ffi_prep_cif returned {status} ({result})"
             )));
         }
 
         Ok(Self {
             cif,
-            arg_types: arg_types_vec,
+            arg_types: arg_types_vec.into_boxed_slice(),
             arg_types_raw: arg_types_raw_vec,
             ret_type,
+            errno: 0,
+            raw: 0,
             phantom: PhantomData,
         })
     }
@@ -317,22 +447,47 @@ where
         R: Default,
         F: Into<*mut c_void>,
     {
-        let mut result: R = Default::default();
+        assert_eq!(
+            arg_values.len(),
+            self.arg_types.len(),
+            "the interface was prepared for another number of arguments"
+        );
+        // libffi widens integral returns smaller than a register to a full
+        // `ffi_arg`, so the return buffer must be at least that large
+        let mut result = [0u64; 2];
         let fn_ptr: *mut c_void = f.into();
         unsafe {
+            // cleared so a stale value isn't mistaken for this call's
+            *libc::__errno_location() = 0;
             ffi_call(
                 &mut self.cif,
                 fn_ptr,
                 if self.ret_type == FfiType::Void {
                     std::ptr::null_mut()
                 } else {
-                    &mut result as *mut _ as *mut c_void
+                    result.as_mut_ptr() as *mut c_void
                 },
                 arg_values.as_ptr() as *mut _,
             );
+            // read before anything else gets a chance to clobber it
+            self.errno = *libc::__errno_location();
         };
-        result
+        self.raw = result[0];
+        if self.ret_type == FfiType::Void {
+            return R::default();
+        }
+        unsafe { std::ptr::read(result.as_ptr() as *const R) }
+    }
+    /// `errno` as the last `call` left it.
+    pub fn errno(&self) -> i32 {
+        self.errno
     }
+
+    /// The return register as the last `call` left it, widened by libffi.
+    pub fn raw_return(&self) -> u64 {
+        self.raw
+    }
+
     pub fn call_args<A>(&mut self, f: impl Into<*mut c_void>, args: A) -> R
     where
         R: Default,
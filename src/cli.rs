@@ -1,11 +1,9 @@
-use std::io::Write;
+use std::fs;
 
 use rustyline::{Config, Editor, history::DefaultHistory};
 
-use crate::lex::{Token, lex};
+use crate::{config::config, helper::ReplHelper, proto::CType, session::Session};
 
-const RED: &str = "\x1b[31m";
-const GREEN: &str = "\x1b[32m";
 const RESET: &str = "\x1b[m";
 
 #[derive(Debug, Default, Clone)]
@@ -18,75 +16,107 @@ pub enum OpMode {
     Void,
 }
 
+impl OpMode {
+    /// Return type assumed for calls to functions without a prototype.
+    pub fn ret_type(&self) -> CType {
+        match self {
+            OpMode::Float => CType::Double,
+            OpMode::Int => CType::Long,
+            OpMode::Ptr => CType::String,
+            OpMode::Char => CType::Char,
+            OpMode::Void => CType::Void,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Cli {
     mode: OpMode,
     counter: i32,
-    rl: Option<Editor<(), DefaultHistory>>,
+    rl: Option<Editor<ReplHelper, DefaultHistory>>,
 }
 
 impl Cli {
-    pub fn new(mode: &OpMode) -> Self {
-        let mut rl = Editor::with_config(
-            Config::builder()
-                .history_ignore_space(true)
-                .max_history_size(1000)
-                .unwrap()
-                .build(),
-        )
-        .unwrap_or_else(|_| Editor::new().unwrap());
-        let _ = rl.load_history("hist.txt");
-        Self {
-            mode: mode.clone(),
+    pub fn new(session: &Session) -> Self {
+        let mut cli = Self {
+            mode: session.mode.clone(),
             counter: 0,
-            rl: Some(rl),
+            rl: Some(new_editor()),
+        };
+        cli.update(session);
+        cli
+    }
+    /// Catches up with the mode, prototypes and libraries of `session`.
+    pub fn update(&mut self, session: &Session) {
+        self.mode = session.mode.clone();
+        if let Some(helper) = self.editor().helper_mut() {
+            helper.update(session);
         }
     }
-    pub fn update_mode(&mut self, mode: &OpMode) {
-        self.mode = mode.clone();
+    pub fn editor(&mut self) -> &mut Editor<ReplHelper, DefaultHistory> {
+        self.rl.get_or_insert_with(new_editor)
     }
-    pub fn editor(&mut self) -> &mut Editor<(), DefaultHistory> {
-        if self.rl.is_none() {
-            let mut rl = Editor::new().unwrap();
-            let _ = rl.load_history("history.txt");
-            self.rl = Some(rl);
+    /// Appends the lines entered since the last save to the history file,
+    /// which keeps the lines other running REPLs appended in the meantime.
+    fn save_history(&mut self) {
+        let path = &config().history_path;
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
         }
-        self.rl.as_mut().unwrap()
+        let _ = self.editor().append_history(path);
     }
 }
 
+/// An editor with the configured history size and the history file loaded.
+fn new_editor() -> Editor<ReplHelper, DefaultHistory> {
+    let config = config();
+    let mut rl = Config::builder()
+        .history_ignore_space(true)
+        .max_history_size(config.history_size)
+        .ok()
+        .and_then(|builder| Editor::with_config(builder.build()).ok())
+        .unwrap_or_else(|| Editor::new().unwrap());
+    let _ = rl.load_history(&config.history_path);
+    rl.set_helper(Some(ReplHelper::default()));
+    rl
+}
+
 impl Iterator for Cli {
-    type Item = Vec<(Token, String)>;
+    type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.counter >= 1000 {
             return None;
         }
-        let mode_text = format!("{GREEN}[{:?}] {RESET}$ ", self.mode);
-        let readline = self.editor().readline(&mode_text);
+        let config = config();
+        let prompt = config.prompt.replace("{mode}", &format!("{:?}", self.mode));
+        let prompt = match config.theme.prompt.is_empty() {
+            true => prompt,
+            false => format!("{}{prompt}{RESET}", config.theme.prompt),
+        };
+        let readline = self.editor().readline(&prompt);
 
         match readline {
             Ok(line) => {
                 let _ = self.editor().add_history_entry(&line);
                 self.counter += 1;
                 if self.counter & 7 == 0 {
-                    let _ = self.editor().save_history("hist.txt");
+                    self.save_history();
                 }
-                let tokens = lex(&line);
-                return Some(tokens);
+                Some(line)
             }
             Err(rustyline::error::ReadlineError::Interrupted) => {
                 eprintln!("to exit press CTRL+D");
-                return Some(Vec::new());
+                Some(String::new())
             }
             Err(rustyline::error::ReadlineError::Eof) => {
                 println!("\nGoodbye!");
-                let _ = self.editor().save_history("hist.txt");
-                return None;
+                self.save_history();
+                None
             }
             Err(e) => {
-                eprintln!("{RED}Cli Error found: {e}{RESET}");
-                return None;
+                eprintln!("{}Cli Error found: {e}{RESET}", config.theme.error);
+                None
             }
         }
     }
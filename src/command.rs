@@ -0,0 +1,811 @@
+use std::{
+    ffi::c_void,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::{
+    bench::bench,
+    call::{arg_value, call, render_call},
+    cffi::{FFI_ABIS, FFI_DEFAULT_ABI, abi_names, parse_abi},
+    cli::OpMode,
+    compile::{Compiled, compile_file, compile_snippet},
+    dlfcn::symbolize,
+    error::Error,
+    eval::{Global, Value, show_result, values_equal},
+    heap::{display_leaks, forget},
+    lex::Token,
+    libpath::{add_search_path, del_search_path, display_search_paths},
+    mem::{Encoding, read_c_string},
+    owned::display_allocs,
+    proto::{CType, Prototype, parse_c_definitions},
+    registry::{CSource, base_name},
+    session::Session,
+    vars::{
+        assert_eval, const_eval, display_all, display_history, display_vars, expr_eval, var_eval,
+    },
+};
+
+/// How much of a string `:str` reads by default.
+const STR_MAX: usize = 4096;
+
+/// How the lexer splits a command's arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgSpec {
+    /// Lexed like a call: identifiers, literals and operators.
+    Exprs,
+    /// Whitespace separated paths, quotes allowed.
+    Paths,
+    /// The rest of the line, untouched, as a single `Raw` token.
+    Raw,
+}
+
+impl ArgSpec {
+    fn describe(&self) -> &'static str {
+        match self {
+            ArgSpec::Exprs => "identifiers, literals and expressions",
+            ArgSpec::Paths => "whitespace separated paths, quote the ones with spaces",
+            ArgSpec::Raw => "the rest of the line, as is",
+        }
+    }
+}
+
+/// A `:` command. Names and aliases include the colon, `:help` lists every
+/// registered command from its usage and help text.
+pub trait Command: Send + Sync {
+    fn name(&self) -> &str;
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+    fn args(&self) -> ArgSpec {
+        ArgSpec::Exprs
+    }
+    fn usage(&self) -> &str;
+    fn help(&self) -> &str;
+    /// Errors blame tokens by their index in `args`.
+    fn run(&self, args: &[(Token, String)], session: &mut Session) -> Result<(), Error>;
+}
+
+type RunFn = fn(&[(Token, String)], &mut Session) -> Result<(), Error>;
+
+struct Builtin {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    args: ArgSpec,
+    usage: &'static str,
+    help: &'static str,
+    run: RunFn,
+}
+
+impl Command for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn aliases(&self) -> &[&str] {
+        self.aliases
+    }
+    fn args(&self) -> ArgSpec {
+        self.args
+    }
+    fn usage(&self) -> &str {
+        self.usage
+    }
+    fn help(&self) -> &str {
+        self.help
+    }
+    fn run(&self, args: &[(Token, String)], session: &mut Session) -> Result<(), Error> {
+        (self.run)(args, session)
+    }
+}
+
+static COMMANDS: OnceLock<Mutex<Vec<Arc<dyn Command>>>> = OnceLock::new();
+
+fn commands() -> &'static Mutex<Vec<Arc<dyn Command>>> {
+    COMMANDS.get_or_init(|| {
+        Mutex::new(
+            builtins()
+                .into_iter()
+                .map(|b| Arc::new(b) as Arc<dyn Command>)
+                .collect(),
+        )
+    })
+}
+
+fn names(cmd: &dyn Command) -> impl Iterator<Item = &str> {
+    std::iter::once(cmd.name()).chain(cmd.aliases().iter().copied())
+}
+
+/// Adds a command, failing when its name or one of its aliases is taken or
+/// is not a `:` followed by an identifier.
+pub fn register_command(cmd: impl Command + 'static) -> Result<(), Error> {
+    let mut commands = commands().lock().unwrap();
+    for name in names(&cmd) {
+        let valid = name.strip_prefix(':').is_some_and(|id| {
+            id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        if !valid {
+            return Err(Error::parse(format!(
+                "`{name}` is not a valid command name"
+            )));
+        }
+        if commands.iter().any(|c| names(&**c).any(|n| n == name)) {
+            return Err(Error::parse(format!("the command `{name}` already exists")));
+        }
+    }
+    commands.push(Arc::new(cmd));
+    Ok(())
+}
+
+/// The command called `name`, or aliased as `name`.
+pub fn find_command(name: &str) -> Option<Arc<dyn Command>> {
+    commands()
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|c| names(&***c).any(|n| n == name))
+        .cloned()
+}
+
+fn syntax_error(usage: &str) -> Error {
+    Error::parse(format!("expected Syntax is `{usage}`"))
+}
+
+fn builtins() -> Vec<Builtin> {
+    vec![
+        Builtin {
+            name: ":help",
+            aliases: &[":h"],
+            args: ArgSpec::Exprs,
+            usage: ":help [command]",
+            help: "lists the commands, or describes one",
+            run: help,
+        },
+        Builtin {
+            name: ":d",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":d",
+            help: "calls without a prototype return a long",
+            run: |_, session| {
+                session.mode = OpMode::Int;
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":f",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":f",
+            help: "calls without a prototype return a double",
+            run: |_, session| {
+                session.mode = OpMode::Float;
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":c",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":c [{ <C source> }]",
+            help: "calls without a prototype return a char, or with a block compiles and links it",
+            run: |args, session| {
+                match args.first() {
+                    Some((Token::Raw, src)) => load_compiled(
+                        session,
+                        compile_snippet(src),
+                        src,
+                        CSource::Snippet(src.clone()),
+                    )?,
+                    _ => session.mode = OpMode::Char,
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":s",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":s",
+            help: "calls without a prototype return a char*",
+            run: |_, session| {
+                session.mode = OpMode::Ptr;
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":v",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":v",
+            help: "calls without a prototype return void",
+            run: |_, session| {
+                session.mode = OpMode::Void;
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":abi",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":abi [name]",
+            help: "lists the calling conventions libffi supports, or selects one, e.g. `efi64`",
+            run: |args, session| {
+                match args {
+                    [] => {
+                        println!("INFO: Listing ABIs: ");
+                        for (name, abi) in FFI_ABIS {
+                            let current = if *abi == session.abi {
+                                " (current)"
+                            } else {
+                                ""
+                            };
+                            let default = if *abi == FFI_DEFAULT_ABI {
+                                " (default)"
+                            } else {
+                                ""
+                            };
+                            println!("\t- {name}{default}{current}");
+                        }
+                    }
+                    [(Token::Id, name)] => {
+                        session.abi = parse_abi(name).ok_or_else(|| {
+                            Error::ffi(format!("unknown ABI `{name}`, see `:abi`")).at(0)
+                        })?;
+                        println!("INFO: calls now use {}", abi_names(session.abi).join("/"));
+                    }
+                    _ => return Err(syntax_error(":abi [name]")),
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":r",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":r [var]",
+            help: "prints the last result, or stores it in `var`",
+            run: |args, session| {
+                let last = match session.env.history.last() {
+                    Some(last) if last.dropped => {
+                        return Err(Error::eval("the last result was dropped"));
+                    }
+                    Some(last) => last.clone(),
+                    None => return Err(Error::eval("no call has returned a value yet")),
+                };
+                match args {
+                    [] => {
+                        println!("{}", show_result(last.ty, &last.value));
+                        Ok(())
+                    }
+                    [var] => session
+                        .env
+                        .set_var(var.1.clone(), last.value)
+                        .map_err(|e| e.at(0)),
+                    _ => Err(syntax_error(":r [var]")),
+                }
+            },
+        },
+        Builtin {
+            name: ":cfile",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":cfile <file.c>...",
+            help: "compiles C files into a shared object and links it",
+            run: |args, session| {
+                for (i, tok) in args.iter().enumerate() {
+                    let src = std::fs::read_to_string(&tok.1)
+                        .map_err(|e| Error::io(format!("Could not read {}: {e}", tok.1)).at(i))?;
+                    // kept absolute so that saved sessions compile it again
+                    // from anywhere
+                    let path = std::fs::canonicalize(&tok.1)
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_else(|_| tok.1.clone());
+                    load_compiled(session, compile_file(&tok.1), &src, CSource::File(path))
+                        .map_err(|e| e.at(i))?;
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":proto",
+            aliases: &[],
+            args: ArgSpec::Raw,
+            usage: ":proto [<name> <ret>(<args>)]",
+            help: "lists the prototypes, or declares the one calls to `name` use",
+            run: |args, session| {
+                let Some((_, sig)) = args.first() else {
+                    session.protos.display();
+                    return Ok(());
+                };
+                let (name, sig) = sig.split_once(char::is_whitespace).unwrap_or((sig, ""));
+                let proto = Prototype::parse(sig).map_err(|e| {
+                    Error::parse(format!(
+                        "{e}, expected Syntax is `:proto <name> <ret>(<args>)`"
+                    ))
+                    .at(0)
+                })?;
+                println!("INFO: registered `{}`", proto.display(name));
+                session.protos.set(name, proto);
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":l",
+            aliases: &[":link"],
+            args: ArgSpec::Paths,
+            usage: ":l [--ns <name>] <lib>...",
+            help: "links shared objects, in a separate link namespace with `--ns`",
+            run: |args, session| {
+                let (ns, first) = match args.first().map(|tok| tok.1.as_str()) {
+                    Some("--ns") => match args.get(1) {
+                        Some(ns) => (Some(ns.1.as_str()), 2),
+                        None => return Err(syntax_error(":l --ns <name> <lib>...")),
+                    },
+                    _ => (None, 0),
+                };
+                for (i, tok) in args.iter().enumerate().skip(first) {
+                    match tok.0 {
+                        Token::FileName => {
+                            session.libs.add_lib_in(&tok.1, ns).map_err(|e| e.at(i))?
+                        }
+                        _ => {
+                            return Err(Error::lex(format!(
+                                "`{}` is not a valid file name!",
+                                tok.1
+                            ))
+                            .at(i));
+                        }
+                    }
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":ul",
+            aliases: &[":unlink"],
+            args: ArgSpec::Paths,
+            usage: ":ul <lib>...",
+            help: "unlinks shared objects",
+            run: |args, session| {
+                for (i, tok) in args.iter().enumerate() {
+                    let ranges = session.libs.del_lib(&tok.1).map_err(|e| e.at(i))?;
+                    for (name, _) in session.env.take_globals_in(&ranges) {
+                        println!("INFO: `{name}` unbound, it lived in {}", tok.1);
+                    }
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":ls",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":ls",
+            help: "lists the linked libraries",
+            run: |_, session| {
+                session.libs.get_libs();
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":libpath",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":libpath [add|rm <dir>...]",
+            help: "lists, adds or removes directories libraries are searched in",
+            run: |args, _| {
+                match args.first().map(|tok| tok.1.as_str()) {
+                    None => display_search_paths(),
+                    Some("add") => {
+                        for (i, tok) in args.iter().enumerate().skip(1) {
+                            add_search_path(&tok.1).map_err(|e| e.at(i))?;
+                        }
+                    }
+                    Some("rm") => {
+                        for (i, tok) in args.iter().enumerate().skip(1) {
+                            del_search_path(&tok.1).map_err(|e| e.at(i))?;
+                        }
+                    }
+                    Some(_) => return Err(syntax_error(":libpath [add|rm <dir>...]")),
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":watch",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":watch [lib...]",
+            help: "lists the watched libraries, or reloads `lib` whenever it is rebuilt",
+            run: |args, session| {
+                if args.is_empty() {
+                    session.libs.get_watched();
+                }
+                for (i, tok) in args.iter().enumerate() {
+                    session.libs.watch_lib(&tok.1).map_err(|e| e.at(i))?;
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":unwatch",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":unwatch <lib>...",
+            help: "stops reloading libraries when they change",
+            run: |args, session| {
+                for (i, tok) in args.iter().enumerate() {
+                    session.libs.unwatch_lib(&tok.1).map_err(|e| e.at(i))?;
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":syms",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":syms <lib> [filter]",
+            help: "lists the symbols a library exports, with their versions",
+            run: |args, session| match args {
+                [lib] => session.libs.list_syms(&lib.1, None).map_err(|e| e.at(0)),
+                [lib, filter] => session
+                    .libs
+                    .list_syms(&lib.1, Some(&filter.1))
+                    .map_err(|e| e.at(0)),
+                _ => Err(syntax_error(":syms <lib> [filter]")),
+            },
+        },
+        Builtin {
+            name: ":sym",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":sym <ptr>...",
+            help: "shows which library and symbol addresses point into",
+            run: |args, session| {
+                if args.is_empty() {
+                    return Err(syntax_error(":sym <ptr>..."));
+                }
+                for (i, tok) in args.iter().enumerate() {
+                    match arg_value(&session.env, tok).map_err(|e| e.at(i))? {
+                        Value::Integer(addr) => println!("{}", symbolize(addr as usize)),
+                        Value::Pointer(addr) => println!("{}", symbolize(addr)),
+                        value => {
+                            return Err(Error::eval(format!("{value:?} is not an address")).at(i));
+                        }
+                    }
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":str",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":str <ptr> [utf8|latin1|hex] [max]",
+            help: "decodes the NUL terminated string at an address, at most `max` bytes of it",
+            run: |args, session| {
+                const USAGE: &str = ":str <ptr> [utf8|latin1|hex] [max]";
+                let addr = match args.first() {
+                    Some(tok) => match arg_value(&session.env, tok).map_err(|e| e.at(0))? {
+                        Value::Pointer(p) => p,
+                        Value::Integer(i) => i as usize,
+                        value => {
+                            return Err(Error::eval(format!("{value:?} is not an address")).at(0));
+                        }
+                    },
+                    None => return Err(syntax_error(USAGE)),
+                };
+                let mut encoding = Encoding::Utf8;
+                let mut max = STR_MAX;
+                for (i, tok) in args.iter().enumerate().skip(1) {
+                    match tok {
+                        (Token::Id, name) if i == 1 => {
+                            encoding = Encoding::parse(name).ok_or_else(|| {
+                                Error::eval(format!("unknown encoding `{name}`")).at(i)
+                            })?;
+                        }
+                        (Token::CInt, n) => {
+                            max = n
+                                .parse()
+                                .map_err(|_| Error::parse(format!("`{n}` is not a count")).at(i))?;
+                        }
+                        _ => return Err(syntax_error(USAGE).at(i)),
+                    }
+                }
+                if addr == 0 {
+                    return Err(Error::memory("NULL pointer").at(0));
+                }
+                let (bytes, truncated) = read_c_string(addr, max)
+                    .ok_or_else(|| Error::memory(format!("{addr:#x} is not readable")).at(0))?;
+                println!("{:?}", encoding.decode(&bytes));
+                if truncated {
+                    println!("INFO: stopped after {max} bytes");
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":const",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":const <name> <expression>",
+            help: "defines a constant",
+            run: |args, session| {
+                println!("{}", const_eval(&mut session.env, args.to_vec())?);
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":var",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":var <name> <expression>",
+            help: "sets a variable",
+            run: |args, session| {
+                println!("{}", var_eval(&mut session.env, args.to_vec())?);
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":global",
+            aliases: &[],
+            args: ArgSpec::Raw,
+            usage: ":global <type> <symbol>",
+            help: "binds a variable exported by a library, reads and writes go to its memory",
+            run: |args, session| {
+                // the type may span several words
+                let (ty, sym) = args
+                    .first()
+                    .and_then(|tok| tok.1.rsplit_once(char::is_whitespace))
+                    .ok_or_else(|| syntax_error(":global <type> <symbol>"))?;
+                let ty = match CType::parse(ty) {
+                    Ok(CType::Void) => return Err(Error::eval("a global can not be void").at(0)),
+                    Ok(ty) => ty,
+                    Err(e) => return Err(Error::parse(e).at(0)),
+                };
+                let addr =
+                    <*mut c_void>::from(session.libs.get_sym(sym).map_err(|e| e.at(0))?) as usize;
+                let name = base_name(sym);
+                session
+                    .env
+                    .set_global(
+                        name.to_string(),
+                        Global {
+                            addr,
+                            ty,
+                            sym: sym.to_string(),
+                        },
+                    )
+                    .map_err(|e| e.at(0))?;
+                println!("INFO: `{name}` bound to {ty} at {}", symbolize(addr));
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":p",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":p <var>...",
+            help: "prints variables, constants and results",
+            run: |args, session| display_vars(&session.env, args.to_vec()),
+        },
+        Builtin {
+            name: ":pa",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":pa",
+            help: "prints every variable, constant and global",
+            run: |_, session| {
+                display_all(&session.env);
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":hist",
+            aliases: &[":history"],
+            args: ArgSpec::Exprs,
+            usage: ":hist",
+            help: "lists the call results, `$n` refers to the nth one and `$_` to the last",
+            run: |_, session| {
+                display_history(&session.env);
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":allocs",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":allocs",
+            help: "lists the pointers returned by `owned(...)` functions that are not freed yet",
+            run: |_, session| {
+                display_allocs(&session.allocs, &session.env);
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":drop",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":drop <$n>...",
+            help: "forgets results, freeing the owned pointers nothing else holds",
+            run: |args, session| {
+                if args.is_empty() {
+                    return Err(syntax_error(":drop <$n>..."));
+                }
+                for (i, tok) in args.iter().enumerate() {
+                    match tok {
+                        (Token::HistRef, name) => {
+                            session.env.drop_result(name).map_err(|e| e.at(i))?
+                        }
+                        _ => return Err(syntax_error(":drop <$n>...").at(i)),
+                    }
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":leaks",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":leaks [clear]",
+            help: "lists the blocks calls allocated and nothing freed, with `--heapcheck`",
+            run: |args, session| match args {
+                [] => {
+                    let dropped = display_leaks(&session.heap, &session.allocs)?;
+                    session.warnings.extend(dropped);
+                    Ok(())
+                }
+                [(Token::Id, clear)] if clear == "clear" => {
+                    forget()?;
+                    println!("INFO: forgot the blocks allocated so far");
+                    Ok(())
+                }
+                _ => Err(syntax_error(":leaks [clear]")),
+            },
+        },
+        Builtin {
+            name: ":bench",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":bench [-n N] [--warmup W] <call>",
+            help: "times a call, FFI setup excluded, and reports ns and cycles per call",
+            run: bench,
+        },
+        Builtin {
+            name: ":trace",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":trace [on [log.jsonl]|off]",
+            help: "reports the arguments, return and errno of every call, also logged as JSON lines",
+            run: |args, session| {
+                match args {
+                    [] => match (session.trace.enabled, session.trace.log_path()) {
+                        (false, _) => println!("INFO: tracing is off"),
+                        (true, None) => println!("INFO: tracing is on"),
+                        (true, Some(log)) => println!("INFO: tracing is on, logging to {log}"),
+                    },
+                    [on] if on.1 == "on" => session.trace.start(None)?,
+                    [on, log] if on.1 == "on" => {
+                        session.trace.start(Some(&log.1)).map_err(|e| e.at(1))?
+                    }
+                    [off] if off.1 == "off" => session.trace.stop(),
+                    _ => return Err(syntax_error(":trace [on [log.jsonl]|off]")),
+                }
+                Ok(())
+            },
+        },
+        Builtin {
+            name: ":save",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":save <file>",
+            help: "writes a script restoring the libraries, prototypes, variables and mode",
+            run: |args, session| match args {
+                [file] => {
+                    session.save(&file.1).map_err(|e| e.at(0))?;
+                    println!("INFO: session saved to {}", file.1);
+                    Ok(())
+                }
+                _ => Err(syntax_error(":save <file>")),
+            },
+        },
+        Builtin {
+            name: ":load",
+            aliases: &[],
+            args: ArgSpec::Paths,
+            usage: ":load <file>",
+            help: "runs a script, such as a session written by `:save`",
+            run: |args, session| match args {
+                [file] => session.load(&file.1).map_err(|e| e.at(0)),
+                _ => Err(syntax_error(":load <file>")),
+            },
+        },
+        Builtin {
+            name: ":assert",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":assert <expression>",
+            help: "fails unless the expression is true",
+            run: |args, session| assert_eval(&session.env, args.to_vec()),
+        },
+        Builtin {
+            name: ":expect",
+            aliases: &[],
+            args: ArgSpec::Exprs,
+            usage: ":expect <call> == <value>",
+            help: "makes a call and fails unless it returns `value`",
+            run: expect,
+        },
+    ]
+}
+
+fn help(args: &[(Token, String)], _: &mut Session) -> Result<(), Error> {
+    let Some((_, name)) = args.first() else {
+        println!("INFO: Listing commands: ");
+        for cmd in commands().lock().unwrap().iter() {
+            println!("\t{:<32} {}", cmd.usage(), cmd.help());
+        }
+        return Ok(());
+    };
+    let name = match name.starts_with(':') {
+        true => name.clone(),
+        false => format!(":{name}"),
+    };
+    let cmd = find_command(&name)
+        .ok_or_else(|| Error::parse(format!("unknown command `{name}`")).at(0))?;
+    println!("{}", cmd.usage());
+    println!("\t{}", cmd.help());
+    if !cmd.aliases().is_empty() {
+        println!("\taliases: {}", cmd.aliases().join(", "));
+    }
+    println!("\targuments: {}", cmd.args().describe());
+    Ok(())
+}
+
+/// `:expect <call> == <value>`
+fn expect(args: &[(Token, String)], session: &mut Session) -> Result<(), Error> {
+    let eq = args
+        .iter()
+        .position(|tok| tok.0 == Token::EqEq)
+        .filter(|eq| *eq > 0 && *eq + 1 < args.len())
+        .ok_or_else(|| syntax_error(":expect <call> == <value>"))?;
+    let expected =
+        expr_eval(&session.env, args[eq + 1..].to_vec()).map_err(|e| e.shifted(eq + 1))?;
+    let call_text = render_call(&args[..eq]);
+    let out = call(session, &args[..eq])?;
+    let Some(value) = out.value else {
+        return Err(Error::assertion(format!("`{call_text}` returned void")));
+    };
+    if !values_equal(&value, &expected) {
+        return Err(Error::assertion(format!(
+            "`{call_text}` returned {}, expected {expected:?}",
+            show_result(out.ty, &value)
+        )));
+    }
+    Ok(())
+}
+
+/// Links a freshly compiled shared object and registers the prototypes of the
+/// functions defined in `src`.
+fn load_compiled(
+    session: &mut Session,
+    compiled: Result<Compiled, Error>,
+    src: &str,
+    source: CSource,
+) -> Result<(), Error> {
+    let compiled = compiled?;
+    session.warnings.extend(compiled.warnings);
+    session.libs.add_compiled(&compiled.so_file, source)?;
+    for (name, proto) in parse_c_definitions(src) {
+        match proto {
+            Ok(proto) => {
+                println!("INFO: registered `{}`", proto.display(&name));
+                session.protos.set(&name, proto);
+            }
+            Err(e) => session.warnings.push(Error::parse(format!(
+                "no prototype registered for `{name}`: {e}"
+            ))),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::{
+    env,
+    ffi::{CString, OsString},
+    fs, io,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::error::Error;
+
+static SNIPPETS: AtomicUsize = AtomicUsize::new(0);
+
+/// The directory sources are compiled in, private to this process: another
+/// user able to swap a `.so` before it is loaded would run code in the REPL.
+/// Created on first use, a later compilation creates a new one once
+/// `remove_work_dir` removed it.
+static WORK_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// A shared object the C compiler built.
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub so_file: String,
+    /// What the compiler warned about, when it did
+    pub warnings: Option<Error>,
+}
+
+/// Creates a new directory only the current user can access, failing
+/// rather than reusing one that exists.
+fn private_dir() -> Result<PathBuf, Error> {
+    let template = env::temp_dir().join("creplrs-XXXXXX");
+    let mut template = CString::new(template.as_os_str().as_bytes())
+        .map_err(|e| Error::io(format!("Invalid temporary directory: {e}")))?
+        .into_bytes_with_nul();
+    // mkdtemp picks a name nobody uses yet and creates it with mode 0700
+    if unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+        return Err(Error::io(format!(
+            "Could not create a directory in {}: {}",
+            env::temp_dir().display(),
+            io::Error::last_os_error()
+        )));
+    }
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+fn work_dir() -> Result<PathBuf, Error> {
+    let mut dir = WORK_DIR.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(dir) = dir.as_ref() {
+        return Ok(dir.clone());
+    }
+    Ok(dir.insert(private_dir()?).clone())
+}
+
+/// Makes `dir`, created by `private_dir` in the process that re-executed
+/// this one, the work directory, for `remove_work_dir` to remove it.
+pub fn adopt_work_dir(dir: PathBuf) {
+    WORK_DIR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert(dir);
+}
+
+/// Removes the sources and objects compiled so far. What is loaded from
+/// there stays mapped.
+pub fn remove_work_dir() -> Result<(), Error> {
+    let dir = WORK_DIR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    match dir {
+        Some(dir) => fs::remove_dir_all(&dir)
+            .map_err(|e| Error::io(format!("Could not remove {}: {e}", dir.display()))),
+        None => Ok(()),
+    }
+}
+
+/// Writes `src` to a temporary file and compiles it to a shared object.
+pub fn compile_snippet(src: &str) -> Result<Compiled, Error> {
+    // every snippet gets its own file name, dlopen would otherwise hand back
+    // the previously loaded image
+    let n = SNIPPETS.fetch_add(1, Ordering::Relaxed) + 1;
+    let dir = work_dir()?;
+    let c_file = dir.join(format!("snippet{n}.c"));
+    fs::write(&c_file, src)
+        .map_err(|e| Error::io(format!("Could not write {}: {e}", c_file.display())))?;
+    compile(&c_file, &dir.join(format!("snippet{n}.so")))
+}
+
+/// Compiles the allocator shim of `--heapcheck`, see `heap.rs`.
+pub fn compile_heap_shim(src: &str) -> Result<Compiled, Error> {
+    let dir = work_dir()?;
+    let c_file = dir.join("heapcheck.c");
+    fs::write(&c_file, src)
+        .map_err(|e| Error::io(format!("Could not write {}: {e}", c_file.display())))?;
+    compile(&c_file, &dir.join("heapcheck.so"))
+}
+
+pub fn compile_file(path: &str) -> Result<Compiled, Error> {
+    let c_file = Path::new(path);
+    let stem = c_file
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "cfile".to_string());
+    let n = SNIPPETS.fetch_add(1, Ordering::Relaxed) + 1;
+    compile(c_file, &work_dir()?.join(format!("{stem}{n}.so")))
+}
+
+/// Runs `$CC` (or `cc`) with `-shared -fPIC`. Its diagnostics are part of the
+/// error when it fails, the warnings of the result otherwise.
+fn compile(c_file: &Path, so_file: &Path) -> Result<Compiled, Error> {
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let output = Command::new(&cc)
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(so_file)
+        .arg(c_file)
+        .output()
+        .map_err(|e| Error::compile(format!("Could not run the C compiler `{cc}`: {e}")))?;
+    let diagnostics = String::from_utf8_lossy(&output.stderr);
+    let diagnostics = diagnostics.trim_end();
+    if !output.status.success() {
+        return Err(Error::compile(format!(
+            "`{cc}` failed to compile {}:\n{diagnostics}",
+            c_file.display()
+        )));
+    }
+    Ok(Compiled {
+        so_file: so_file.display().to_string(),
+        warnings: (!diagnostics.is_empty()).then(|| {
+            Error::compile(format!(
+                "`{cc}` warned about {}:\n{diagnostics}",
+                c_file.display()
+            ))
+        }),
+    })
+}
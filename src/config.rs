@@ -0,0 +1,456 @@
+use std::{env, fmt::Display, fs, num::NonZeroUsize, path::PathBuf, sync::OnceLock};
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+    cli::OpMode,
+    error::Error,
+    libpath::expand_home,
+    proto::{Prototype, parse_c_declarations},
+    session::Session,
+};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Escape sequences of the colours the front end uses, empty for none.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub prompt: String,
+    pub error: String,
+    pub result: String,
+    pub command: String,
+    /// Unknown commands and symbols
+    pub unknown: String,
+    /// Functions that can be called
+    pub symbol: String,
+    pub identifier: String,
+    pub string: String,
+    pub number: String,
+    pub hint: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            prompt: "\x1b[32m".to_string(),
+            error: "\x1b[31m".to_string(),
+            result: "\x1b[34m".to_string(),
+            command: "\x1b[35m".to_string(),
+            unknown: "\x1b[31m".to_string(),
+            symbol: "\x1b[32m".to_string(),
+            identifier: "\x1b[36m".to_string(),
+            string: "\x1b[33m".to_string(),
+            number: "\x1b[34m".to_string(),
+            hint: "\x1b[2m".to_string(),
+        }
+    }
+}
+
+impl Theme {
+    /// No colours at all.
+    fn none() -> Self {
+        Self {
+            prompt: String::new(),
+            error: String::new(),
+            result: String::new(),
+            command: String::new(),
+            unknown: String::new(),
+            symbol: String::new(),
+            identifier: String::new(),
+            string: String::new(),
+            number: String::new(),
+            hint: String::new(),
+        }
+    }
+}
+
+/// Settings read from `$XDG_CONFIG_HOME/creplrs/config.toml`:
+///
+/// ```toml
+/// [history]
+/// path = "~/.local/state/creplrs/history"
+/// size = 1000
+///
+/// [repl]
+/// mode = "int"            # int, float, char, ptr or void
+/// prompt = "[{mode}] $ "
+///
+/// [startup]
+/// libraries = ["m", "~/build/libfoo.so"]
+/// prototypes = ["~/protos.txt"]   # `<name> <ret>(<args>)` lines
+/// headers = ["~/src/foo/foo.h"]
+///
+/// [theme]
+/// enabled = true          # false turns every colour off
+/// prompt = "bold green"   # colour names, SGR codes or "none"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub history_path: PathBuf,
+    pub history_size: usize,
+    pub mode: Option<OpMode>,
+    /// `{mode}` is replaced by the current mode
+    pub prompt: String,
+    pub libraries: Vec<String>,
+    pub prototypes: Vec<String>,
+    pub headers: Vec<String>,
+    pub theme: Theme,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            history_path: state_dir().join("history"),
+            history_size: 1000,
+            mode: None,
+            prompt: "[{mode}] $ ".to_string(),
+            libraries: Vec::new(),
+            prototypes: Vec::new(),
+            headers: Vec::new(),
+            theme: Theme::default(),
+        }
+    }
+}
+
+/// `$var/creplrs`, `~/<fallback>/creplrs` when it is not set.
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    let base = env::var(var)
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(expand_home(&format!("~/{fallback}"))));
+    base.join("creplrs")
+}
+
+/// Where the history and other state go, `$XDG_STATE_HOME/creplrs`.
+pub fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+pub fn config_path() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("config.toml")
+}
+
+/// The configuration, the defaults until `init_config` read the file.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Reads the config file, when there is one. A file that does not parse is
+/// reported and the defaults are used instead.
+pub fn init_config() -> Result<(), Error> {
+    let path = config_path();
+    let (config, res) = match fs::read_to_string(&path) {
+        Ok(src) => match parse_config(&src) {
+            Ok(config) => (config, Ok(())),
+            Err(e) => (
+                Config::default(),
+                Err(Error::new(e.kind, format!("{}: {}", path.display(), e.msg))),
+            ),
+        },
+        Err(_) => (Config::default(), Ok(())),
+    };
+    let _ = CONFIG.set(config);
+    res
+}
+
+/// The file as written, every section and setting being optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    history: HistorySection,
+    repl: ReplSection,
+    startup: StartupSection,
+    theme: ThemeSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HistorySection {
+    path: Option<String>,
+    size: Option<NonZeroUsize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ReplSection {
+    mode: Option<Spanned<String>>,
+    prompt: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StartupSection {
+    libraries: Vec<String>,
+    prototypes: Vec<String>,
+    headers: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeSection {
+    enabled: Option<bool>,
+    prompt: Option<Spanned<String>>,
+    error: Option<Spanned<String>>,
+    result: Option<Spanned<String>>,
+    command: Option<Spanned<String>>,
+    unknown: Option<Spanned<String>>,
+    symbol: Option<Spanned<String>>,
+    identifier: Option<Spanned<String>>,
+    string: Option<Spanned<String>>,
+    number: Option<Spanned<String>>,
+    hint: Option<Spanned<String>>,
+}
+
+/// A parse error at byte `at` of `src`, reported by line.
+fn error_at(src: &str, at: usize, msg: impl Display) -> Error {
+    let n = src[..at.min(src.len())].matches('\n').count() + 1;
+    Error::parse(format!("line {n}: {msg}"))
+}
+
+/// `"bold green"` -> `"\x1b[1;32m"`, `"none"` -> `""`.
+fn parse_colour(spec: &str) -> Result<String, String> {
+    let mut codes = Vec::new();
+    for word in spec.split_whitespace() {
+        let code = match word {
+            "none" => continue,
+            "bold" => "1",
+            "dim" => "2",
+            "italic" => "3",
+            "underline" => "4",
+            "black" => "30",
+            "red" => "31",
+            "green" => "32",
+            "yellow" => "33",
+            "blue" => "34",
+            "magenta" => "35",
+            "cyan" => "36",
+            "white" => "37",
+            "bright_black" | "gray" | "grey" => "90",
+            "bright_red" => "91",
+            "bright_green" => "92",
+            "bright_yellow" => "93",
+            "bright_blue" => "94",
+            "bright_magenta" => "95",
+            "bright_cyan" => "96",
+            "bright_white" => "97",
+            code if code.split(';').all(|c| c.parse::<u8>().is_ok()) => code,
+            _ => return Err(format!("unknown colour `{word}`")),
+        };
+        codes.push(code);
+    }
+    Ok(match codes.is_empty() {
+        true => String::new(),
+        false => format!("\x1b[{}m", codes.join(";")),
+    })
+}
+
+pub fn parse_mode(mode: &str) -> Result<OpMode, String> {
+    Ok(match mode {
+        "int" => OpMode::Int,
+        "float" => OpMode::Float,
+        "char" => OpMode::Char,
+        "ptr" => OpMode::Ptr,
+        "void" => OpMode::Void,
+        _ => {
+            return Err(format!(
+                "unknown mode `{mode}`, expected int, float, char, ptr or void"
+            ));
+        }
+    })
+}
+
+/// Parses a setting of the file `src`, reporting errors at its line.
+fn parse_spanned<T>(
+    src: &str,
+    value: Spanned<String>,
+    parse: fn(&str) -> Result<T, String>,
+) -> Result<T, Error> {
+    parse(value.get_ref()).map_err(|e| error_at(src, value.span().start, e))
+}
+
+fn parse_config(src: &str) -> Result<Config, Error> {
+    let file: ConfigFile = toml::from_str(src).map_err(|e| match e.span() {
+        Some(span) => error_at(src, span.start, e.message().trim_end()),
+        None => Error::parse(e.message().trim_end().to_string()),
+    })?;
+    let mut config = Config::default();
+    if let Some(path) = file.history.path {
+        config.history_path = PathBuf::from(expand_home(&path));
+    }
+    if let Some(size) = file.history.size {
+        config.history_size = size.get();
+    }
+    if let Some(mode) = file.repl.mode {
+        config.mode = Some(parse_spanned(src, mode, parse_mode)?);
+    }
+    if let Some(prompt) = file.repl.prompt {
+        config.prompt = prompt;
+    }
+    config.libraries = file.startup.libraries;
+    config.prototypes = file.startup.prototypes;
+    config.headers = file.startup.headers;
+
+    let colours = file.theme;
+    if colours.enabled == Some(false) {
+        config.theme = Theme::none();
+        return Ok(config);
+    }
+    let theme = &mut config.theme;
+    for (colour, field) in [
+        (colours.prompt, &mut theme.prompt),
+        (colours.error, &mut theme.error),
+        (colours.result, &mut theme.result),
+        (colours.command, &mut theme.command),
+        (colours.unknown, &mut theme.unknown),
+        (colours.symbol, &mut theme.symbol),
+        (colours.identifier, &mut theme.identifier),
+        (colours.string, &mut theme.string),
+        (colours.number, &mut theme.number),
+        (colours.hint, &mut theme.hint),
+    ] {
+        if let Some(colour) = colour {
+            *field = parse_spanned(src, colour, parse_colour)?;
+        }
+    }
+    Ok(config)
+}
+
+/// Sets the mode, links the libraries and imports the prototypes and
+/// headers the config lists, returning what could not be.
+pub fn apply_startup(config: &Config, session: &mut Session) -> Vec<Error> {
+    let mut errors = Vec::new();
+    if let Some(mode) = &config.mode {
+        session.mode = mode.clone();
+    }
+    for lib in &config.libraries {
+        if let Err(e) = session.libs.add_lib(lib) {
+            errors.push(e);
+        }
+    }
+    for file in &config.prototypes {
+        let file = expand_home(file);
+        let src = match fs::read_to_string(&file) {
+            Ok(src) => src,
+            Err(e) => {
+                errors.push(Error::io(format!("Could not read {file}: {e}")));
+                continue;
+            }
+        };
+        let mut count = 0;
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, sig) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match Prototype::parse(sig) {
+                Ok(proto) => {
+                    session.protos.set(name, proto);
+                    count += 1;
+                }
+                Err(e) => errors.push(Error::parse(format!("{file}:{}: {e}", i + 1))),
+            }
+        }
+        println!("INFO: imported {count} prototype(s) from {file}");
+    }
+    for file in &config.headers {
+        let file = expand_home(file);
+        let src = match fs::read_to_string(&file) {
+            Ok(src) => src,
+            Err(e) => {
+                errors.push(Error::io(format!("Could not read {file}: {e}")));
+                continue;
+            }
+        };
+        let (mut count, mut skipped) = (0, 0);
+        for (name, proto) in parse_c_declarations(&src) {
+            match proto {
+                Ok(proto) => {
+                    session.protos.set(&name, proto);
+                    count += 1;
+                }
+                Err(_) => skipped += 1,
+            }
+        }
+        println!(
+            "INFO: imported {count} prototype(s) from {file}, skipped {skipped} with unsupported types"
+        );
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(src: &str) -> String {
+        parse_config(src).unwrap_err().msg
+    }
+
+    #[test]
+    fn reads_every_section() {
+        let config = parse_config(
+            r##"
+            [history]
+            path = "/tmp/history"   # where it goes
+            size = 50
+
+            [repl]
+            mode = "int"
+            prompt = "#> "
+
+            [startup]
+            libraries = [
+                "m",   # the [maths] library
+                "/opt/lib[1].so",
+            ]
+            headers = ['~/foo.h']
+
+            [theme]
+            prompt = "bold green"
+            error = "none"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.history_path, PathBuf::from("/tmp/history"));
+        assert_eq!(config.history_size, 50);
+        assert!(matches!(config.mode, Some(OpMode::Int)));
+        assert_eq!(config.prompt, "#> ");
+        assert_eq!(config.libraries, ["m", "/opt/lib[1].so"]);
+        assert!(config.prototypes.is_empty());
+        assert_eq!(config.headers, ["~/foo.h"]);
+        assert_eq!(config.theme.prompt, "\x1b[1;32m");
+        assert_eq!(config.theme.error, "");
+        assert_eq!(config.theme.result, Theme::default().result);
+    }
+
+    #[test]
+    fn defaults_and_disabled_theme() {
+        let config = parse_config("").unwrap();
+        assert_eq!(config.history_size, 1000);
+        assert!(config.mode.is_none());
+        let config = parse_config("[theme]\nenabled = false\nprompt = \"red\"\n").unwrap();
+        assert_eq!(config.theme.prompt, "");
+        assert_eq!(config.theme.error, "");
+    }
+
+    #[test]
+    fn reports_errors_by_line() {
+        assert!(parse_err("[repl]\nmoed = \"int\"\n").starts_with("line 2: unknown field `moed`"));
+        assert!(parse_err("[repl]\nmode = \"x\"\nmode = \"int\"\n").starts_with("line 3:"));
+        assert!(parse_err("[startup]\nlibraries = [\"a\" \"b\"]\n").starts_with("line 2:"));
+        assert!(parse_err("[history]\nsize = 0\n").starts_with("line 2:"));
+        assert!(parse_err("[history]\nsize = \"big\"\n").starts_with("line 2:"));
+        assert!(parse_err("[startup]\nlibraries = \"m\"\n").starts_with("line 2:"));
+        assert_eq!(
+            parse_err("[repl]\n\nmode = \"fast\"\n"),
+            "line 3: unknown mode `fast`, expected int, float, char, ptr or void"
+        );
+        assert_eq!(
+            parse_err("[theme]\nhint = \"blinking\"\n"),
+            "line 2: unknown colour `blinking`"
+        );
+    }
+}
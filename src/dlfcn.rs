@@ -1,7 +1,8 @@
 use std::{
     error::Error,
-    ffi::{CString, c_char, c_int, c_void},
+    ffi::{CStr, CString, c_char, c_int, c_long, c_void},
     fmt::{self, Debug, Display, Formatter},
+    ops::Range,
     result::Result,
 };
 
@@ -18,13 +19,43 @@ pub enum DlOpenFlags {
     RTLD_DEEPBIND = 0x8,
 }
 
+/// Link-map list (namespace) identifier, as taken by `dlmopen`.
+pub type Lmid = c_long;
+
+pub const LM_ID_BASE: Lmid = 0;
+pub const LM_ID_NEWLM: Lmid = -1;
+
 unsafe extern "C" {
     fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlmopen(lmid: Lmid, filename: *const c_char, flags: c_int) -> *mut c_void;
     fn dlclose(handle: *mut c_void) -> c_int;
     fn dlsym(handle: *const c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlvsym(handle: *const c_void, symbol: *const c_char, version: *const c_char) -> *mut c_void;
+    fn dlinfo(handle: *mut c_void, request: c_int, info: *mut c_void) -> c_int;
+    fn dladdr(addr: *const c_void, info: *mut DlInfo) -> c_int;
+}
+
+#[repr(C)]
+struct DlInfo {
+    dli_fname: *const c_char,
+    dli_fbase: *mut c_void,
+    dli_sname: *const c_char,
+    dli_saddr: *mut c_void,
 }
 
-pub struct DlError(String);
+const RTLD_DI_LMID: c_int = 1;
+const RTLD_DI_LINKMAP: c_int = 2;
+
+#[repr(C)]
+struct LinkMap {
+    l_addr: usize,
+    l_name: *const c_char,
+    l_ld: *mut c_void,
+    l_next: *mut LinkMap,
+    l_prev: *mut LinkMap,
+}
+
+pub struct DlError(pub(crate) String);
 impl Display for DlError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "DL error: {}", self.0)
@@ -65,6 +96,42 @@ impl DynLib {
         Ok(Self { handle })
     }
 
+    /// Opens the library in the link namespace `lmid`, `LM_ID_NEWLM` creating
+    /// a fresh one with its own copy of every dependency.
+    pub fn open_in_namespace(
+        filename: &str,
+        lmid: Lmid,
+        flags: &[DlOpenFlags],
+    ) -> Result<Self, DlError> {
+        let c_filename = CString::new(filename)
+            .map_err(|e| DlError::new(&format!("Error {}, Invalid Filename {}", e, filename)))?;
+        let combined_flags = flags.iter().fold(0, |acc, flag| acc | *flag as c_int);
+        let handle = unsafe { dlmopen(lmid, c_filename.as_ptr(), combined_flags) };
+        if handle.is_null() {
+            return Err(DlError(format!(
+                "Error Opening the shared object: {} in namespace {}",
+                filename, lmid
+            )));
+        }
+        Ok(Self { handle })
+    }
+
+    /// The link namespace the library was loaded into.
+    pub fn namespace(&self) -> Option<Lmid> {
+        let mut lmid: Lmid = 0;
+        if unsafe {
+            dlinfo(
+                self.handle,
+                RTLD_DI_LMID,
+                &mut lmid as *mut _ as *mut c_void,
+            )
+        } != 0
+        {
+            return None;
+        }
+        Some(lmid)
+    }
+
     pub fn close(&self) -> Result<(), DlError> {
         if unsafe { dlclose(self.handle) } == 0 {
             return Ok(());
@@ -74,6 +141,67 @@ impl DynLib {
             self
         )))
     }
+
+    fn link_map(&self) -> Option<&LinkMap> {
+        let mut lm: *mut LinkMap = std::ptr::null_mut();
+        if unsafe {
+            dlinfo(
+                self.handle,
+                RTLD_DI_LINKMAP,
+                &mut lm as *mut _ as *mut c_void,
+            )
+        } != 0
+        {
+            return None;
+        }
+        unsafe { lm.as_ref() }
+    }
+
+    /// Path the loader actually opened, even when the library was found
+    /// through its search path.
+    pub fn file_name(&self) -> Option<String> {
+        let lm = self.link_map()?;
+        if lm.l_name.is_null() {
+            return None;
+        }
+        Some(
+            unsafe { CStr::from_ptr(lm.l_name) }
+                .to_string_lossy()
+                .to_string(),
+        )
+    }
+
+    /// Address ranges of the `PT_LOAD` segments this library is mapped at.
+    pub fn mapped_ranges(&self) -> Vec<Range<usize>> {
+        let Some(lm) = self.link_map() else {
+            return Vec::new();
+        };
+        let mut search = (lm.l_addr, Vec::new());
+        unsafe {
+            libc::dl_iterate_phdr(Some(collect_segments), &mut search as *mut _ as *mut c_void)
+        };
+        search.1
+    }
+}
+
+unsafe extern "C" fn collect_segments(
+    info: *mut libc::dl_phdr_info,
+    _size: usize,
+    data: *mut c_void,
+) -> c_int {
+    let (base, ranges) = unsafe { &mut *(data as *mut (usize, Vec<Range<usize>>)) };
+    let info = unsafe { &*info };
+    if info.dlpi_addr as usize != *base {
+        return 0;
+    }
+    for i in 0..info.dlpi_phnum as usize {
+        let phdr = unsafe { &*info.dlpi_phdr.add(i) };
+        if phdr.p_type == libc::PT_LOAD {
+            let start = *base + phdr.p_vaddr as usize;
+            ranges.push(start..start + phdr.p_memsz as usize);
+        }
+    }
+    1
 }
 
 #[derive(Debug)]
@@ -91,6 +219,36 @@ impl DlSym {
         }
         Ok(Self { fn_ptr: found_sym })
     }
+
+    /// Looks up a specific version of a symbol, e.g. `memcpy` at `GLIBC_2.14`.
+    pub fn new_versioned(lib: &DynLib, symbol: &str, version: &str) -> Result<Self, DlError> {
+        let c_sym = CString::new(symbol)
+            .map_err(|e| DlError(format!("Error {}: Invalid Symbol name `{}`", e, symbol)))?;
+        let c_version = CString::new(version)
+            .map_err(|e| DlError(format!("Error {}: Invalid Symbol version `{}`", e, version)))?;
+        let found_sym = unsafe { dlvsym(lib.handle, c_sym.as_ptr(), c_version.as_ptr()) };
+        if found_sym.is_null() {
+            return Err(DlError("Could not find the symbol version".to_string()));
+        }
+        Ok(Self { fn_ptr: found_sym })
+    }
+
+    /// Looks `symbol` up in the global scope, preloaded objects first, the
+    /// way the dynamic linker binds undefined references.
+    pub fn global(symbol: &str) -> Result<Self, DlError> {
+        let c_sym = CString::new(symbol)
+            .map_err(|e| DlError(format!("Error {}: Invalid Symbol name `{}`", e, symbol)))?;
+        // RTLD_DEFAULT
+        let found_sym = unsafe { dlsym(std::ptr::null(), c_sym.as_ptr()) };
+        if found_sym.is_null() {
+            return Err(DlError("Could not find the symbol".to_string()));
+        }
+        Ok(Self { fn_ptr: found_sym })
+    }
+
+    pub fn addr(&self) -> usize {
+        self.fn_ptr as usize
+    }
 }
 
 impl From<DlSym> for *mut c_void {
@@ -104,3 +262,50 @@ impl Drop for DynLib {
         unsafe { dlclose(self.handle) };
     }
 }
+
+/// What `dladdr` knows about an address.
+#[derive(Debug, Clone)]
+pub struct DlAddr {
+    pub file: String,
+    pub base: usize,
+    pub symbol: Option<String>,
+    pub sym_addr: usize,
+}
+
+impl DlAddr {
+    pub fn lookup(addr: usize) -> Option<Self> {
+        let mut info: DlInfo = unsafe { std::mem::zeroed() };
+        if unsafe { dladdr(addr as *const c_void, &mut info) } == 0 || info.dli_fname.is_null() {
+            return None;
+        }
+        let file = unsafe { CStr::from_ptr(info.dli_fname) }
+            .to_string_lossy()
+            .to_string();
+        let symbol = (!info.dli_sname.is_null()).then(|| {
+            unsafe { CStr::from_ptr(info.dli_sname) }
+                .to_string_lossy()
+                .to_string()
+        });
+        Some(Self {
+            file,
+            base: info.dli_fbase as usize,
+            symbol,
+            sym_addr: info.dli_saddr as usize,
+        })
+    }
+}
+
+/// Formats an address as `0x7f... <libc.so.6!malloc+0x12>`, or just the
+/// address when it is not inside any loaded object.
+pub fn symbolize(addr: usize) -> String {
+    let Some(info) = DlAddr::lookup(addr) else {
+        return format!("{addr:#x}");
+    };
+    let lib = info.file.rsplit('/').next().unwrap_or(&info.file);
+    let location = match &info.symbol {
+        Some(sym) if addr == info.sym_addr => format!("{lib}!{sym}"),
+        Some(sym) => format!("{lib}!{sym}+{:#x}", addr - info.sym_addr),
+        None => format!("{lib}+{:#x}", addr - info.base),
+    };
+    format!("{addr:#x} <{location}>")
+}
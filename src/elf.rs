@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, fs};
+
+const SHT_DYNSYM: u32 = 11;
+const SHT_GNU_VERDEF: u32 = 0x6fff_fffd;
+const SHT_GNU_VERSYM: u32 = 0x6fff_ffff;
+const VERSYM_HIDDEN: u16 = 0x8000;
+const VER_FLG_BASE: u16 = 0x1;
+
+/// A version a dynamic symbol is exported under, `default` being the one
+/// plain `dlsym` and the static linker pick (`name@@VERSION`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymVersion {
+    pub version: String,
+    pub default: bool,
+}
+
+struct Section {
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+    entsize: usize,
+}
+
+struct Elf<'a> {
+    data: &'a [u8],
+    sections: Vec<Section>,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, String> {
+        if data.len() < 64 || data[..4] != *b"\x7fELF" {
+            return Err("not an ELF file".to_string());
+        }
+        // only ELFCLASS64, ELFDATA2LSB
+        if data[4] != 2 || data[5] != 1 {
+            return Err("only little-endian 64-bit ELF files are supported".to_string());
+        }
+        let mut elf = Self {
+            data,
+            sections: Vec::new(),
+        };
+        let shoff = elf.u64(0x28)? as usize;
+        let shentsize = elf.u16(0x3a)? as usize;
+        let shnum = elf.u16(0x3c)? as usize;
+        for i in 0..shnum {
+            let sh = offset(shoff, i.checked_mul(shentsize).ok_or_else(malformed)?)?;
+            elf.sections.push(Section {
+                kind: elf.u32(offset(sh, 4)?)?,
+                offset: elf.u64(offset(sh, 0x18)?)? as usize,
+                size: elf.u64(offset(sh, 0x20)?)? as usize,
+                link: elf.u32(offset(sh, 0x28)?)? as usize,
+                entsize: elf.u64(offset(sh, 0x38)?)? as usize,
+            });
+        }
+        Ok(elf)
+    }
+
+    fn bytes(&self, at: usize, len: usize) -> Result<&'a [u8], String> {
+        self.data
+            .get(at..offset(at, len)?)
+            .ok_or_else(|| "truncated ELF file".to_string())
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(at, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(at, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(at, 8)?.try_into().unwrap()))
+    }
+
+    /// The string at `at` in `strtab`, cut at the end of the table.
+    fn str_at(&self, strtab: &Section, at: usize) -> String {
+        let table = offset(strtab.offset, strtab.size)
+            .ok()
+            .and_then(|end| self.data.get(strtab.offset..end))
+            .unwrap_or_default();
+        let bytes = table.get(at..).unwrap_or_default();
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).to_string()
+    }
+
+    fn section(&self, kind: u32) -> Option<&Section> {
+        self.sections.iter().find(|s| s.kind == kind)
+    }
+
+    /// The section `section` links to, its string table for most.
+    fn linked(&self, section: &Section) -> Result<&Section, String> {
+        self.sections.get(section.link).ok_or_else(malformed)
+    }
+
+    /// Version index -> version name, from `.gnu.version_d`.
+    fn verdefs(&self) -> Result<BTreeMap<u16, String>, String> {
+        let mut out = BTreeMap::new();
+        let Some(verdef) = self.section(SHT_GNU_VERDEF) else {
+            return Ok(out);
+        };
+        let strtab = self.linked(verdef)?;
+        let end = offset(verdef.offset, verdef.size)?;
+        let mut at = verdef.offset;
+        while at < end {
+            let flags = self.u16(offset(at, 2)?)?;
+            let index = self.u16(offset(at, 4)?)?;
+            let aux = self.u32(offset(at, 12)?)? as usize;
+            let next = self.u32(offset(at, 16)?)? as usize;
+            // the base entry names the object itself, not a symbol version
+            if flags & VER_FLG_BASE == 0 {
+                let name = self.u32(offset(at, aux)?)? as usize;
+                out.insert(index, self.str_at(strtab, name));
+            }
+            if next == 0 {
+                break;
+            }
+            at = offset(at, next)?;
+        }
+        Ok(out)
+    }
+}
+
+fn malformed() -> String {
+    "malformed ELF file".to_string()
+}
+
+/// `base + add`, an error rather than an overflow for offsets read from the
+/// file.
+fn offset(base: usize, add: usize) -> Result<usize, String> {
+    base.checked_add(add).ok_or_else(malformed)
+}
+
+/// Lists the symbols a shared object defines, with the versions each one is
+/// exported under.
+pub fn dynamic_symbols(path: &str) -> Result<BTreeMap<String, Vec<SymVersion>>, String> {
+    let data = fs::read(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    let elf = Elf::parse(&data).map_err(|e| format!("{path}: {e}"))?;
+    let dynsym = elf
+        .section(SHT_DYNSYM)
+        .ok_or_else(|| format!("{path}: no dynamic symbol table"))?;
+    let dynstr = elf.linked(dynsym).map_err(|e| format!("{path}: {e}"))?;
+    let versym = elf.section(SHT_GNU_VERSYM);
+    let verdefs = elf.verdefs().map_err(|e| format!("{path}: {e}"))?;
+
+    let mut out: BTreeMap<String, Vec<SymVersion>> = BTreeMap::new();
+    let count = dynsym.size / dynsym.entsize.max(1);
+    let entry = |base: usize, i: usize, size: usize| {
+        i.checked_mul(size)
+            .ok_or_else(malformed)
+            .and_then(|at| offset(base, at))
+            .map_err(|e| format!("{path}: {e}"))
+    };
+    for i in 1..count {
+        let sym = entry(dynsym.offset, i, dynsym.entsize)?;
+        let shndx = elf.u16(offset(sym, 6)?)?;
+        if shndx == 0 {
+            // undefined, imported from somewhere else
+            continue;
+        }
+        let name = elf.str_at(dynstr, elf.u32(sym)? as usize);
+        if name.is_empty() {
+            continue;
+        }
+        let versions = out.entry(name).or_default();
+        let Some(versym) = versym else {
+            continue;
+        };
+        let raw = elf.u16(entry(versym.offset, i, 2)?)?;
+        if let Some(version) = verdefs.get(&(raw & !VERSYM_HIDDEN)) {
+            versions.push(SymVersion {
+                version: version.clone(),
+                default: raw & VERSYM_HIDDEN == 0,
+            });
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf, process::Command};
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        assert!(Elf::parse(b"#!/bin/sh\n").is_err());
+        let mut header = vec![0; 64];
+        header[..4].copy_from_slice(b"\x7fELF");
+        header[4] = 1;
+        assert!(Elf::parse(&header).is_err());
+        // sections past the end of the file
+        header[4] = 2;
+        header[5] = 1;
+        header[0x28] = 0x40;
+        header[0x3a] = 0x40;
+        header[0x3c] = 1;
+        assert!(Elf::parse(&header).is_err());
+    }
+
+    /// Builds a library exporting `f@V1`, `f@@V2` and `plain@@V2` in a
+    /// directory of its own, named after `test`, returning its path.
+    fn versioned_lib(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("creplrs-{test}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (src, script, so) = (dir.join("v.c"), dir.join("v.map"), dir.join("libv.so"));
+        fs::write(
+            &src,
+            "int f_old(void) { return 1; }\n\
+             int f_new(void) { return 2; }\n\
+             int plain(void) { return 0; }\n\
+             __asm__(\".symver f_old, f@V1\");\n\
+             __asm__(\".symver f_new, f@@V2\");\n",
+        )
+        .unwrap();
+        fs::write(
+            &script,
+            "V1 { local: f_old; f_new; };\nV2 { global: plain; } V1;\n",
+        )
+        .unwrap();
+        let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&so)
+            .arg(&src)
+            .arg(format!("-Wl,--version-script={}", script.display()))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        so
+    }
+
+    #[test]
+    fn reads_symbol_versions() {
+        let so = versioned_lib("elf-versions");
+        let symbols = dynamic_symbols(so.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(so.parent().unwrap()).unwrap();
+        let version = |version: &str, default| SymVersion {
+            version: version.to_string(),
+            default,
+        };
+        let mut f = symbols["f"].clone();
+        f.sort_by(|a, b| a.version.cmp(&b.version));
+        assert_eq!(f, vec![version("V1", false), version("V2", true)]);
+        assert_eq!(symbols["plain"], vec![version("V2", true)]);
+        assert!(!symbols.contains_key("f_old"));
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let so = versioned_lib("elf-malformed");
+        let good = fs::read(&so).unwrap();
+        let broken = so.with_file_name("broken.so");
+        let read = |data: &[u8]| {
+            fs::write(&broken, data).unwrap();
+            dynamic_symbols(broken.to_str().unwrap())
+        };
+
+        // the section headers come last, cutting anything off loses them
+        for len in (0..good.len()).step_by(97) {
+            assert!(read(&good[..len]).is_err(), "truncated to {len} bytes");
+        }
+
+        let elf = Elf::parse(&good).unwrap();
+        let header = |kind: u32| {
+            let i = elf.sections.iter().position(|s| s.kind == kind).unwrap();
+            let shoff = u64::from_le_bytes(good[0x28..0x30].try_into().unwrap()) as usize;
+            shoff + i * u16::from_le_bytes(good[0x3a..0x3c].try_into().unwrap()) as usize
+        };
+        let patched = |at: usize, value: &[u8]| {
+            let mut data = good.clone();
+            data[at..at + value.len()].copy_from_slice(value);
+            read(&data)
+        };
+        // links to a section that does not exist
+        assert!(patched(header(SHT_DYNSYM) + 0x28, &1000u32.to_le_bytes()).is_err());
+        assert!(patched(header(SHT_GNU_VERDEF) + 0x28, &1000u32.to_le_bytes()).is_err());
+        // offsets that overflow or point past the end
+        assert!(patched(header(SHT_GNU_VERDEF) + 0x18, &u64::MAX.to_le_bytes()).is_err());
+        assert!(patched(header(SHT_DYNSYM) + 0x18, &(u64::MAX - 8).to_le_bytes()).is_err());
+        // the aux entry of `V1`, the first one is the base entry
+        let verdef = elf.section(SHT_GNU_VERDEF).unwrap().offset;
+        let v1 = verdef + elf.u32(verdef + 16).unwrap() as usize;
+        assert!(patched(v1 + 12, &u32::MAX.to_le_bytes()).is_err());
+        fs::remove_dir_all(so.parent().unwrap()).unwrap();
+    }
+}
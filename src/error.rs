@@ -0,0 +1,134 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::Range,
+};
+
+use crate::{cffi::FfiError, dlfcn::DlError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Lex,
+    Parse,
+    Eval,
+    /// Resolving, opening or watching a library
+    Link,
+    Symbol,
+    Ffi,
+    /// Reading or writing memory the REPL does not own
+    Memory,
+    Compile,
+    Io,
+    /// A failed `:assert` or `:expect`
+    Assertion,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ErrorKind::Lex => "lex",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Eval => "eval",
+            ErrorKind::Link => "link",
+            ErrorKind::Symbol => "symbol",
+            ErrorKind::Ffi => "FFI",
+            ErrorKind::Memory => "memory",
+            ErrorKind::Compile => "compile",
+            ErrorKind::Io => "I/O",
+            ErrorKind::Assertion => "assertion",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+/// An error of any layer, with where in the line it comes from when that is
+/// known. Errors are raised against the index of the offending token, the
+/// session turns that into a byte `span` of the line it evaluated.
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub msg: String,
+    pub token: Option<usize>,
+    pub span: Option<Range<usize>>,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Assertion => write!(f, "assertion failed: {}", self.msg),
+            kind => write!(f, "{kind} error: {}", self.msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn new(kind: ErrorKind, msg: impl Into<String>) -> Self {
+        Self {
+            kind,
+            msg: msg.into(),
+            token: None,
+            span: None,
+        }
+    }
+
+    pub fn lex(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Lex, msg)
+    }
+    pub fn parse(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Parse, msg)
+    }
+    pub fn eval(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Eval, msg)
+    }
+    pub fn link(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Link, msg)
+    }
+    pub fn symbol(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Symbol, msg)
+    }
+    pub fn ffi(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Ffi, msg)
+    }
+    pub fn memory(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Memory, msg)
+    }
+    pub fn compile(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Compile, msg)
+    }
+    pub fn io(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Io, msg)
+    }
+    pub fn assertion(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Assertion, msg)
+    }
+
+    /// Blames the token at `index`, unless a more precise one already is.
+    pub fn at(mut self, index: usize) -> Self {
+        self.token.get_or_insert(index);
+        self
+    }
+
+    /// For errors raised against a slice of the tokens starting at `offset`.
+    pub fn shifted(mut self, offset: usize) -> Self {
+        self.token = self.token.map(|index| index + offset);
+        self
+    }
+
+    pub fn with_span(mut self, span: Range<usize>) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl From<DlError> for Error {
+    fn from(value: DlError) -> Self {
+        Self::link(value.0)
+    }
+}
+
+impl From<FfiError> for Error {
+    fn from(value: FfiError) -> Self {
+        Self::ffi(value.0)
+    }
+}
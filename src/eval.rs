@@ -1,6 +1,17 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fmt::{self, Display, Formatter},
+    ops::Range,
+};
 
-use crate::parser::{BinaryOp, Expr, UnaryOp};
+use crate::{
+    dlfcn::symbolize,
+    error::Error,
+    mem::{preview, read_c_string},
+    parser::{BinaryOp, Expr, UnaryOp},
+    proto::CType,
+};
 
 #[derive(Debug, Clone)]
 pub enum Value {
@@ -9,75 +20,293 @@ pub enum Value {
     Number(f64),
     Integer(i64),
     Bool(bool),
+    /// An address returned by a library, never read unless asked to
+    Pointer(usize),
 }
 
-#[derive(Clone, Debug)]
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::CString(s) => write!(f, "{s}"),
+            Value::CChar(c) => write!(f, "{c}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Integer(i) => write!(f, "{i}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Pointer(p) => write!(f, "{p:#x}"),
+        }
+    }
+}
+
+/// `==` between two values, integers and floats comparing numerically.
+pub fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::Integer(a), Value::Number(b)) | (Value::Number(b), Value::Integer(a)) => {
+            *a as f64 == *b
+        }
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::CString(a), Value::CString(b)) => a == b,
+        (Value::CChar(a), Value::CChar(b)) => a == b,
+        (Value::Pointer(a), Value::Pointer(b)) => a == b,
+        (Value::Pointer(p), Value::Integer(i)) | (Value::Integer(i), Value::Pointer(p)) => {
+            *p as i64 == *i
+        }
+        // the string the pointer points to, read no further than needed
+        (Value::Pointer(p), Value::CString(s)) | (Value::CString(s), Value::Pointer(p)) => {
+            read_c_string(*p, s.len()).is_some_and(|(bytes, more)| !more && bytes == s.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+/// A call result, kept with the type it was returned as.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub call: String,
+    pub ty: CType,
+    pub value: Value,
+    /// Set by `:drop`, the result can no longer be referred to and no
+    /// longer keeps an owned pointer alive
+    pub dropped: bool,
+}
+
+/// How a call result of type `ty` is printed.
+pub fn show_result(ty: CType, value: &Value) -> String {
+    match (ty, value) {
+        (CType::String, Value::Pointer(0)) => "(NullString)".to_string(),
+        (CType::String, Value::Pointer(p)) => match preview(*p) {
+            Some(preview) => format!("{p:#x} {preview}"),
+            None => format!("{p:#x} (unreadable)"),
+        },
+        (CType::String, Value::CString(s)) => format!("{s:?}"),
+        (_, Value::Pointer(p)) => symbolize(*p),
+        (CType::Char, Value::CChar(c)) => format!("'{c}' ({})", *c as u8),
+        _ => value.to_string(),
+    }
+}
+
+/// A variable living in a library's memory, bound with `:global`.
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub addr: usize,
+    pub ty: CType,
+    /// The symbol as bound, to look it up again once its library reloads
+    pub sym: String,
+}
+
+impl Global {
+    pub fn load(&self) -> Value {
+        let addr = self.addr;
+        unsafe {
+            match self.ty {
+                CType::Char => Value::CChar(*(addr as *const u8) as char),
+                CType::UChar => Value::Integer(*(addr as *const u8) as i64),
+                CType::Short => Value::Integer(*(addr as *const i16) as i64),
+                CType::UShort => Value::Integer(*(addr as *const u16) as i64),
+                CType::Int => Value::Integer(*(addr as *const i32) as i64),
+                CType::UInt => Value::Integer(*(addr as *const u32) as i64),
+                CType::Long => Value::Integer(*(addr as *const i64)),
+                CType::ULong => Value::Integer(*(addr as *const u64) as i64),
+                CType::Float => Value::Number(*(addr as *const f32) as f64),
+                CType::Double => Value::Number(*(addr as *const f64)),
+                CType::String | CType::Pointer | CType::Void => {
+                    Value::Pointer(*(addr as *const usize))
+                }
+            }
+        }
+    }
+
+    /// Writes `value` to the global. A string stored in a `char*` comes back,
+    /// it must outlive the library's use of it.
+    pub fn store(&self, value: &Value) -> Result<Option<CString>, Error> {
+        let int = match value {
+            Value::Integer(i) => Some(*i),
+            Value::Pointer(p) => Some(*p as i64),
+            Value::CChar(c) => Some(*c as i64),
+            Value::Bool(b) => Some(*b as i64),
+            _ => None,
+        };
+        let float = match value {
+            Value::Number(n) => Some(*n),
+            Value::Integer(i) => Some(*i as f64),
+            _ => None,
+        };
+        let mismatch = || Error::eval(format!("can not store {value:?} in a `{}`", self.ty));
+        let addr = self.addr;
+        unsafe {
+            match self.ty {
+                CType::Char | CType::UChar => *(addr as *mut u8) = int.ok_or_else(mismatch)? as u8,
+                CType::Short | CType::UShort => {
+                    *(addr as *mut u16) = int.ok_or_else(mismatch)? as u16
+                }
+                CType::Int | CType::UInt => *(addr as *mut u32) = int.ok_or_else(mismatch)? as u32,
+                CType::Long | CType::ULong => {
+                    *(addr as *mut u64) = int.ok_or_else(mismatch)? as u64
+                }
+                CType::Float => *(addr as *mut f32) = float.ok_or_else(mismatch)? as f32,
+                CType::Double => *(addr as *mut f64) = float.ok_or_else(mismatch)?,
+                CType::String => match value {
+                    Value::CString(s) => {
+                        let s =
+                            CString::new(s.clone()).map_err(|e| Error::memory(e.to_string()))?;
+                        *(addr as *mut *const std::ffi::c_char) = s.as_ptr();
+                        return Ok(Some(s));
+                    }
+                    _ => *(addr as *mut usize) = int.ok_or_else(mismatch)? as usize,
+                },
+                CType::Pointer | CType::Void => {
+                    *(addr as *mut usize) = int.ok_or_else(mismatch)? as usize
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Env {
     pub vars: HashMap<String, Value>,
     pub consts: HashMap<String, Value>,
+    pub globals: HashMap<String, Global>,
+    /// Strings stored in `char*` globals, by the address of the global. One
+    /// is freed once another value is stored there or its library goes.
+    global_strings: HashMap<usize, CString>,
+    /// Results addressable as `$1`, `$2`, ... and `$_` for the last one.
+    pub history: Vec<HistoryEntry>,
 }
 
 impl Env {
     pub fn new() -> Self {
-        Self {
-            vars: HashMap::new(),
-            consts: HashMap::new(),
-        }
+        Self::default()
     }
     pub fn display(&self) {
         println!("{:?}", self)
     }
 
-    pub fn set_var(&mut self, name: String, value: Value) -> Result<(), String> {
+    pub fn set_var(&mut self, name: String, value: Value) -> Result<(), Error> {
         if self.consts.contains_key(&name) {
-            return Err(format!("'{}' is a constant, cannot reassign", name));
+            return Err(Error::eval(format!(
+                "'{}' is a constant, cannot reassign",
+                name
+            )));
+        }
+        if let Some(global) = self.globals.get(&name) {
+            match global.store(&value)? {
+                Some(s) => self.global_strings.insert(global.addr, s),
+                None => self.global_strings.remove(&global.addr),
+            };
+            return Ok(());
         }
         self.vars.insert(name, value);
         Ok(())
     }
 
-    pub fn set_const(&mut self, name: String, value: Value) -> Result<(), String> {
-        if self.consts.contains_key(&name) || self.vars.contains_key(&name) {
-            return Err(format!("'{}' already defined", name));
+    pub fn set_const(&mut self, name: String, value: Value) -> Result<(), Error> {
+        if self.consts.contains_key(&name)
+            || self.vars.contains_key(&name)
+            || self.globals.contains_key(&name)
+        {
+            return Err(Error::eval(format!("'{}' already defined", name)));
         }
         self.consts.insert(name, value);
         Ok(())
     }
 
+    pub fn set_global(&mut self, name: String, global: Global) -> Result<(), Error> {
+        if self.consts.contains_key(&name) || self.vars.contains_key(&name) {
+            return Err(Error::eval(format!("'{}' already defined", name)));
+        }
+        self.globals.insert(name, global);
+        Ok(())
+    }
+
+    /// Unbinds the globals living inside one of `ranges`, the image of a
+    /// library being unlinked or reloaded, returning them sorted by name.
+    pub fn take_globals_in(&mut self, ranges: &[Range<usize>]) -> Vec<(String, Global)> {
+        let inside = |addr: usize| ranges.iter().any(|r| r.contains(&addr));
+        let mut taken: Vec<(String, Global)> = self
+            .globals
+            .extract_if(|_, global| inside(global.addr))
+            .collect();
+        taken.sort_by(|a, b| a.0.cmp(&b.0));
+        self.global_strings.retain(|addr, _| !inside(*addr));
+        taken
+    }
+
+    /// `errno` is a constant as far as the user is concerned, only the call
+    /// path updates it.
+    pub fn set_errno(&mut self, errno: i32) {
+        self.consts
+            .insert("errno".to_string(), Value::Integer(errno as i64));
+    }
+
+    /// Records a call result, returning the `$n` it can be referred to by.
+    pub fn push_history(&mut self, entry: HistoryEntry) -> usize {
+        self.history.push(entry);
+        self.history.len()
+    }
+
+    /// The index in `history` of the result `$n` or `$_`.
+    fn result_index(&self, name: &str) -> Option<usize> {
+        match name.strip_prefix('$')? {
+            "_" => self.history.len().checked_sub(1),
+            n => n
+                .parse::<usize>()
+                .ok()?
+                .checked_sub(1)
+                .filter(|i| *i < self.history.len()),
+        }
+    }
+
+    /// Drops the result `name`, `$n` or `$_`, see `HistoryEntry::dropped`.
+    pub fn drop_result(&mut self, name: &str) -> Result<(), Error> {
+        let entry = self
+            .result_index(name)
+            .map(|i| &mut self.history[i])
+            .filter(|entry| !entry.dropped)
+            .ok_or_else(|| Error::eval(format!("no result `{name}`")))?;
+        entry.dropped = true;
+        Ok(())
+    }
+
     pub fn get(&self, name: &str) -> Option<Value> {
+        if name.starts_with('$') {
+            let entry = self.result_index(name).map(|i| &self.history[i]);
+            return entry.filter(|e| !e.dropped).map(|e| e.value.clone());
+        }
         self.consts
             .get(name)
             .or_else(|| self.vars.get(name))
             .cloned()
+            .or_else(|| self.globals.get(name).map(Global::load))
     }
 }
 
-pub fn eval(expr: &Expr, env: &Env) -> Result<Value, String> {
+pub fn eval(expr: &Expr, env: &Env) -> Result<Value, Error> {
     match expr {
         Expr::Integer(i) => Ok(Value::Integer(*i)),
         Expr::Number(n) => Ok(Value::Number(*n)),
         Expr::CChar(c) => Ok(Value::CChar(*c)),
         Expr::CString(s) => Ok(Value::CString(s.clone())),
-        Expr::Variable(name) => env
+        Expr::Variable(name, at) => env
             .get(name)
-            .ok_or_else(|| format!("Undefined variable: '{}'", name)),
+            .ok_or_else(|| Error::eval(format!("Undefined variable: '{}'", name)).at(*at)),
         Expr::Unary(op, expr) => {
             let val = eval(expr, env)?;
             match op {
                 UnaryOp::Neg => match val {
                     Value::Integer(i) => Ok(Value::Integer(-i)),
                     Value::Number(n) => Ok(Value::Number(-n)),
-                    _ => Err("Cannot negate this type".to_string()),
+                    _ => Err(Error::eval("Cannot negate this type")),
                 },
                 UnaryOp::Not => {
                     let b = match val {
                         Value::Bool(b) => b,
                         Value::Integer(i) => i != 0,
                         Value::Number(n) => n != 0.0,
-                        _ => {
-                            eprintln!("\x1b[31mSyntax Error: !<string>\x1b[m");
-                            false
-                        }
+                        Value::Pointer(p) => p != 0,
+                        _ => return Err(Error::eval("`!` can not be applied to a string")),
                     };
                     Ok(Value::Bool(!b))
                 }
@@ -113,14 +342,24 @@ pub fn eval(expr: &Expr, env: &Env) -> Result<Value, String> {
                         out.push(b);
                         out
                     })),
-                    _ => Err("Cannot add these types".to_string()),
+                    (Value::Pointer(p), Value::Integer(i))
+                    | (Value::Integer(i), Value::Pointer(p)) => {
+                        Ok(Value::Pointer(p.wrapping_add_signed(i as isize)))
+                    }
+                    _ => Err(Error::eval("Cannot add these types")),
                 },
                 BinaryOp::Sub => match (left_val, right_val) {
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a - b)),
                     (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
                     (Value::Integer(a), Value::Number(b)) => Ok(Value::Number(a as f64 - b)),
                     (Value::Number(a), Value::Integer(b)) => Ok(Value::Number(a - b as f64)),
-                    _ => Err("Cannot subtract these types".to_string()),
+                    (Value::Pointer(p), Value::Integer(i)) => {
+                        Ok(Value::Pointer(p.wrapping_add_signed(-i as isize)))
+                    }
+                    (Value::Pointer(a), Value::Pointer(b)) => {
+                        Ok(Value::Integer(a.wrapping_sub(b) as i64))
+                    }
+                    _ => Err(Error::eval("Cannot subtract these types")),
                 },
                 BinaryOp::Mul => match (left_val, right_val) {
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a * b)),
@@ -133,12 +372,12 @@ pub fn eval(expr: &Expr, env: &Env) -> Result<Value, String> {
                     (Value::CChar(a), Value::Integer(b)) => {
                         Ok(Value::CString(a.to_string().repeat(b as usize)))
                     }
-                    _ => Err("Cannot multiply these types".to_string()),
+                    _ => Err(Error::eval("Cannot multiply these types")),
                 },
                 BinaryOp::Div => match (left_val, right_val) {
                     (Value::Integer(a), Value::Integer(b)) => {
                         if b == 0 {
-                            Err("Division by zero".to_string())
+                            Err(Error::eval("Division by zero"))
                         } else {
                             // Return float for division to match C behavior
                             Ok(Value::Number(a as f64 / b as f64))
@@ -146,54 +385,65 @@ pub fn eval(expr: &Expr, env: &Env) -> Result<Value, String> {
                     }
                     (Value::Number(a), Value::Number(b)) => {
                         if b == 0.0 {
-                            Err("Division by zero".to_string())
+                            Err(Error::eval("Division by zero"))
                         } else {
                             Ok(Value::Number(a / b))
                         }
                     }
-                    _ => Err("Cannot divide these types".to_string()),
+                    _ => Err(Error::eval("Cannot divide these types")),
                 },
-                BinaryOp::Eq => {
-                    let eq = match (&left_val, &right_val) {
-                        (Value::Integer(a), Value::Integer(b)) => a == b,
-                        (Value::Number(a), Value::Number(b)) => a == b,
-                        (Value::Bool(a), Value::Bool(b)) => a == b,
-                        (Value::CString(a), Value::CString(b)) => a == b,
-                        _ => false,
-                    };
-                    Ok(Value::Bool(eq))
-                }
-                BinaryOp::Ne => {
-                    let eq = match (&left_val, &right_val) {
-                        (Value::Integer(a), Value::Integer(b)) => a != b,
-                        (Value::Number(a), Value::Number(b)) => a != b,
-                        (Value::Bool(a), Value::Bool(b)) => a != b,
-                        (Value::CString(a), Value::CString(b)) => a != b,
-                        _ => false,
-                    };
-                    Ok(Value::Bool(!eq))
-                }
+                BinaryOp::Eq => Ok(Value::Bool(values_equal(&left_val, &right_val))),
+                BinaryOp::Ne => Ok(Value::Bool(!values_equal(&left_val, &right_val))),
                 BinaryOp::Lt => match (left_val, right_val) {
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Bool(a < b)),
                     (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a < b)),
-                    _ => Err("Cannot compare these types".to_string()),
+                    _ => Err(Error::eval("Cannot compare these types")),
                 },
                 BinaryOp::Le => match (left_val, right_val) {
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Bool(a <= b)),
                     (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a <= b)),
-                    _ => Err("Cannot compare these types".to_string()),
+                    _ => Err(Error::eval("Cannot compare these types")),
                 },
                 BinaryOp::Gt => match (left_val, right_val) {
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Bool(a > b)),
                     (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a > b)),
-                    _ => Err("Cannot compare these types".to_string()),
+                    _ => Err(Error::eval("Cannot compare these types")),
                 },
                 BinaryOp::Ge => match (left_val, right_val) {
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Bool(a >= b)),
                     (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a >= b)),
-                    _ => Err("Cannot compare these types".to_string()),
+                    _ => Err(Error::eval("Cannot compare these types")),
                 },
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::{CStr, c_char};
+
+    #[test]
+    fn globals_keep_the_strings_stored_in_them() {
+        let slot: Box<*const c_char> = Box::new(std::ptr::null());
+        let addr = &*slot as *const *const c_char as usize;
+        let mut env = Env::new();
+        let global = Global {
+            addr,
+            ty: CType::String,
+            sym: "name".to_string(),
+        };
+        env.set_global("name".to_string(), global).unwrap();
+        for s in ["hello", "world"] {
+            env.set_var("name".to_string(), Value::CString(s.to_string()))
+                .unwrap();
+            assert_eq!(unsafe { CStr::from_ptr(*slot) }.to_str(), Ok(s));
+            assert_eq!(env.global_strings.len(), 1);
+        }
+        let image = addr..addr + 8;
+        let taken = env.take_globals_in(std::slice::from_ref(&image));
+        assert_eq!(taken.len(), 1);
+        assert!(env.globals.is_empty() && env.global_strings.is_empty());
+    }
+}
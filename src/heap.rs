@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap, env, ffi::CStr, os::unix::process::CommandExt, path::Path,
+    process::Command, sync::OnceLock,
+};
+
+use crate::{
+    compile::{adopt_work_dir, compile_heap_shim, remove_work_dir},
+    dlfcn::{DlAddr, DlSym, symbolize},
+    error::Error,
+    owned::Allocs,
+};
+
+/// The allocator shim `--heapcheck` preloads.
+const SHIM_SRC: &str = include_str!("heapcheck.c");
+
+/// Set for the process `--heapcheck` re-executes, so that a shim failing to
+/// load does not make it re-execute forever.
+const REEXEC_VAR: &str = "CREPLRS_HEAPCHECK";
+
+/// Most misuses the shim keeps, `MISUSES` in `heapcheck.c`.
+const MISUSES: usize = 64;
+
+/// A block allocated during a call and not freed since.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Block {
+    pub addr: usize,
+    pub size: usize,
+    /// Return address of the `malloc` call
+    pub site: usize,
+    /// The call it was allocated in, see `HeapLog`
+    pub call: u64,
+}
+
+/// A block freed twice.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Misuse {
+    pub addr: usize,
+    /// Return address of the second `free`
+    pub site: usize,
+    /// Return address of the first `free`
+    pub freed_at: usize,
+    /// The call it happened in, 0 outside of calls
+    pub call: u64,
+}
+
+/// The entry points of the preloaded shim.
+struct Shim {
+    begin: extern "C" fn() -> u64,
+    end: extern "C" fn(),
+    live: extern "C" fn(*mut Block, usize) -> usize,
+    misuses: extern "C" fn(usize, *mut Misuse) -> usize,
+    forget: extern "C" fn(),
+    /// Blocks not recorded because the shim's table was full
+    dropped: extern "C" fn() -> usize,
+}
+
+/// The function of the shim named `name`, typed as `F`, an `extern "C" fn`.
+fn lookup<F: Copy>(name: &CStr) -> Option<F> {
+    let sym = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    (!sym.is_null()).then(|| unsafe { std::mem::transmute_copy::<*mut libc::c_void, F>(&sym) })
+}
+
+fn shim() -> Option<&'static Shim> {
+    static SHIM: OnceLock<Option<Shim>> = OnceLock::new();
+    SHIM.get_or_init(|| {
+        Some(Shim {
+            begin: lookup(c"crepl_heap_begin")?,
+            end: lookup(c"crepl_heap_end")?,
+            live: lookup(c"crepl_heap_live")?,
+            misuses: lookup(c"crepl_heap_misuses")?,
+            forget: lookup(c"crepl_heap_forget")?,
+            dropped: lookup(c"crepl_heap_dropped")?,
+        })
+    })
+    .as_ref()
+}
+
+/// Functions the shim interposes.
+const INTERPOSED: &[&str] = &["malloc", "calloc", "realloc", "free"];
+
+/// The shim's definition of `sym` when it interposes it, which calls from
+/// the REPL must go through as well, library handles would bypass it.
+pub fn interposed(sym: &str) -> Option<DlSym> {
+    match enabled() && INTERPOSED.contains(&sym) {
+        true => DlSym::global(sym).ok(),
+        false => None,
+    }
+}
+
+/// Whether the process runs with the shim preloaded.
+pub fn enabled() -> bool {
+    shim().is_some()
+}
+
+/// Starts recording the allocations of this thread, returning the number of
+/// the call they are recorded under, `None` without `--heapcheck`.
+pub fn begin() -> Option<u64> {
+    shim().map(|shim| (shim.begin)())
+}
+
+pub fn end() {
+    if let Some(shim) = shim() {
+        (shim.end)()
+    }
+}
+
+/// The blocks allocated during calls that were not freed yet.
+fn live_blocks(shim: &Shim) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    loop {
+        // more may have been allocated meanwhile, by another thread
+        let n = (shim.live)(blocks.as_mut_ptr(), blocks.len());
+        if n <= blocks.len() {
+            blocks.truncate(n);
+            return blocks;
+        }
+        blocks = vec![Block::default(); n + 16];
+    }
+}
+
+/// Compiles the shim and re-executes CREPLrs with it preloaded, with the
+/// same arguments. Only returns when that fails.
+///
+/// The shim is compiled in the private work directory, which the new process
+/// takes over, see `settle`. It must run before any `Session` exists, one
+/// dropped later would remove the directory.
+pub fn reexec_with_shim() -> Error {
+    if env::var_os(REEXEC_VAR).is_some() {
+        return Error::link(
+            "`--heapcheck`: the allocator shim did not load, is LD_PRELOAD honoured?",
+        );
+    }
+    let e = match compile_heap_shim(SHIM_SRC) {
+        Ok(shim) => exec_with(shim.so_file),
+        Err(e) => e,
+    };
+    // the directory only outlives this process through `exec`
+    let _ = remove_work_dir();
+    e
+}
+
+fn exec_with(shim: String) -> Error {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return Error::io(format!("Could not find the CREPLrs executable: {e}")),
+    };
+    let preload = match env::var("LD_PRELOAD") {
+        Ok(preload) if !preload.is_empty() => format!("{shim}:{preload}"),
+        _ => shim,
+    };
+    let mut args = env::args_os();
+    let e = Command::new(exe)
+        .arg0(args.next().unwrap_or_default())
+        .args(args)
+        .env("LD_PRELOAD", preload)
+        .env(REEXEC_VAR, "1")
+        .exec();
+    Error::io(format!(
+        "Could not re-execute CREPLrs for `--heapcheck`: {e}"
+    ))
+}
+
+/// In the process `--heapcheck` re-executed, takes over the directory the
+/// shim was compiled in, removed along with the session, and takes the shim
+/// out of the environment again: the C compiler and other children are not
+/// to load it, least of all once the directory is gone.
+pub fn settle() {
+    let Some(shim) = shim() else {
+        return;
+    };
+    if env::var_os(REEXEC_VAR).is_none() {
+        return;
+    }
+    let Some(file) = DlAddr::lookup(shim.begin as usize).map(|info| info.file) else {
+        return;
+    };
+    let preload: Vec<String> = env::var("LD_PRELOAD")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|path| !path.is_empty() && *path != file)
+        .map(str::to_string)
+        .collect();
+    // SAFETY: called first thing in `main`, before any thread is spawned
+    unsafe {
+        match preload.is_empty() {
+            true => env::remove_var("LD_PRELOAD"),
+            false => env::set_var("LD_PRELOAD", preload.join(":")),
+        }
+        env::remove_var(REEXEC_VAR);
+    }
+    let path = Path::new(&file);
+    if path.file_name().is_some_and(|name| name == "heapcheck.so")
+        && let Some(dir) = path.parent()
+    {
+        adopt_work_dir(dir.to_path_buf());
+    }
+}
+
+/// What `:leaks` needs to tell which call a block comes from.
+#[derive(Debug, Default)]
+pub struct HeapLog {
+    /// The calls allocations were recorded in, as typed
+    calls: HashMap<u64, String>,
+    /// Misuses already reported
+    misuses_seen: usize,
+}
+
+impl HeapLog {
+    pub fn record_call(&mut self, call: u64, text: &str) {
+        self.calls.insert(call, text.to_string());
+    }
+
+    fn call_text(&self, call: u64) -> &str {
+        match call {
+            0 => "outside of calls",
+            call => self
+                .calls
+                .get(&call)
+                .map_or("an unknown call", String::as_str),
+        }
+    }
+
+    /// A warning for every double free since the last time.
+    pub fn misuses(&mut self) -> Vec<Error> {
+        let Some(shim) = shim() else {
+            return Vec::new();
+        };
+        let mut misuses = [Misuse::default(); MISUSES];
+        let total = (shim.misuses)(self.misuses_seen, misuses.as_mut_ptr());
+        let new = total.saturating_sub(self.misuses_seen).min(MISUSES);
+        self.misuses_seen = total;
+        misuses[..new]
+            .iter()
+            .map(|misuse| {
+                Error::memory(format!(
+                    "double free of {:#x} by {} in `{}`, first freed by {}",
+                    misuse.addr,
+                    symbolize(misuse.site),
+                    self.call_text(misuse.call),
+                    symbolize(misuse.freed_at)
+                ))
+            })
+            .collect()
+    }
+}
+
+/// `:leaks`: the blocks allocated during calls and not freed since, by call.
+/// Returns a warning when some could not be recorded.
+pub fn display_leaks(log: &HeapLog, allocs: &Allocs) -> Result<Option<Error>, Error> {
+    let shim = shim().ok_or_else(not_enabled)?;
+    let mut blocks = live_blocks(shim);
+    blocks.sort_by_key(|b| (b.call, b.addr));
+    let total: usize = blocks.iter().map(|b| b.size).sum();
+    println!(
+        "INFO: Listing leaks, {total} bytes in {} block(s): ",
+        blocks.len()
+    );
+    for block in &blocks {
+        let owned = match allocs.live().iter().any(|a| a.addr == block.addr) {
+            true => ", owned by the REPL",
+            false => "",
+        };
+        println!(
+            "\t- {} bytes at {:#x} from `{}`, allocated by {}{owned}",
+            block.size,
+            block.addr,
+            log.call_text(block.call),
+            symbolize(block.site)
+        );
+    }
+    let dropped = (shim.dropped)();
+    Ok((dropped > 0).then(|| {
+        Error::memory(format!(
+            "{dropped} more block(s) were allocated while too many were recorded, they are not listed"
+        ))
+    }))
+}
+
+/// `:leaks clear`: forgets the blocks allocated so far, they are no longer
+/// reported.
+pub fn forget() -> Result<(), Error> {
+    let shim = shim().ok_or_else(not_enabled)?;
+    (shim.forget)();
+    Ok(())
+}
+
+fn not_enabled() -> Error {
+    Error::eval("allocations are only recorded when CREPLrs runs with `--heapcheck`")
+}
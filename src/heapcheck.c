@@ -0,0 +1,289 @@
+/* Allocator shim preloaded by `CREPLrs --heapcheck`, see heap.rs.
+ *
+ * Blocks allocated while a call is recorded are kept in a table until they
+ * are freed, whenever and by whoever that happens. Freed blocks stay in the
+ * table as dead entries, held back from the allocator until FREED more have
+ * been freed, so that their address is not handed out again meanwhile and
+ * freeing one of them again is caught and reported. */
+#include <pthread.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <string.h>
+
+extern void *__libc_malloc(size_t size);
+extern void *__libc_calloc(size_t n, size_t size);
+extern void *__libc_realloc(void *ptr, size_t size);
+extern void __libc_free(void *ptr);
+
+/* a power of two, filled to three quarters at most */
+#define SLOTS (1 << 16)
+#define MAX_USED (SLOTS / 4 * 3)
+/* dead entries held back to catch double frees */
+#define FREED 1024
+#define MISUSES 64
+
+enum state { EMPTY, LIVE, DEAD };
+
+struct slot {
+    void *ptr;
+    size_t size;
+    /* where it was allocated, or freed once dead */
+    void *site;
+    unsigned long call;
+    enum state state;
+};
+
+/* what crepl_heap_live hands out, mirrored by `Block` */
+struct crepl_block {
+    void *ptr;
+    size_t size;
+    void *site;
+    unsigned long call;
+};
+
+/* a double free, mirrored by `Misuse` */
+struct crepl_misuse {
+    void *ptr;
+    void *site;
+    /* where it was freed the first time */
+    void *freed_at;
+    unsigned long call;
+};
+
+static struct slot table[SLOTS];
+static size_t used;
+/* blocks not recorded because the table was full */
+static size_t dropped;
+static void *freed[FREED];
+static size_t freed_next;
+static struct crepl_misuse misuses[MISUSES];
+static size_t misuse_count;
+static unsigned long calls;
+static pthread_mutex_t lock = PTHREAD_MUTEX_INITIALIZER;
+/* the call the thread is in, 0 outside of calls */
+static __thread unsigned long recording __attribute__((tls_model("initial-exec")));
+
+static size_t home(void *ptr) {
+    return (size_t)(((uintptr_t)ptr >> 4) * 0x9E3779B97F4A7C15ull >> 48) & (SLOTS - 1);
+}
+
+/* the slot of `ptr`, or the empty one it would go to */
+static size_t find(void *ptr) {
+    size_t i = home(ptr);
+    while (table[i].state != EMPTY && table[i].ptr != ptr)
+        i = (i + 1) & (SLOTS - 1);
+    return i;
+}
+
+/* empties slot `i`, moving back the entries that probed past it */
+static void erase(size_t i) {
+    size_t j = i;
+    table[i].state = EMPTY;
+    used--;
+    for (;;) {
+        j = (j + 1) & (SLOTS - 1);
+        if (table[j].state == EMPTY)
+            return;
+        size_t k = home(table[j].ptr);
+        if (i <= j ? (i < k && k <= j) : (i < k || k <= j))
+            continue;
+        table[i] = table[j];
+        table[j].state = EMPTY;
+        i = j;
+    }
+}
+
+static void insert(void *ptr, size_t size, void *site, unsigned long call) {
+    size_t i = find(ptr);
+    if (table[i].state != EMPTY) {
+        erase(i);
+        i = find(ptr);
+    }
+    if (used >= MAX_USED) {
+        dropped++;
+        return;
+    }
+    table[i] = (struct slot){ptr, size, site, call, LIVE};
+    used++;
+}
+
+static int active(void) {
+    return recording || __atomic_load_n(&used, __ATOMIC_RELAXED);
+}
+
+static void note_alloc(void *ptr, size_t size, void *site) {
+    if (!ptr || !active())
+        return;
+    pthread_mutex_lock(&lock);
+    if (recording) {
+        insert(ptr, size, site, recording);
+    } else {
+        /* the address came back, whatever the table says about it is stale */
+        size_t i = find(ptr);
+        if (table[i].state != EMPTY)
+            erase(i);
+    }
+    pthread_mutex_unlock(&lock);
+}
+
+static void note_misuse(void *ptr, void *site, void *freed_at) {
+    misuses[misuse_count % MISUSES] = (struct crepl_misuse){ptr, site, freed_at, recording};
+    misuse_count++;
+}
+
+/* whether `ptr` may be passed on to the allocator, not when it is held
+ * back or was freed already */
+static int note_free(void *ptr, void *site) {
+    if (!active())
+        return 1;
+    int ok = 1;
+    void *evicted = NULL;
+    pthread_mutex_lock(&lock);
+    size_t i = find(ptr);
+    if (table[i].state == LIVE) {
+        table[i].state = DEAD;
+        table[i].site = site;
+        evicted = freed[freed_next];
+        freed[freed_next] = ptr;
+        freed_next = (freed_next + 1) % FREED;
+        size_t j = evicted ? find(evicted) : 0;
+        if (evicted && table[j].state == DEAD)
+            erase(j);
+        ok = 0;
+    } else if (table[i].state == DEAD) {
+        note_misuse(ptr, site, table[i].site);
+        ok = 0;
+    }
+    pthread_mutex_unlock(&lock);
+    __libc_free(evicted);
+    return ok;
+}
+
+void *malloc(size_t size) {
+    void *ptr = __libc_malloc(size);
+    note_alloc(ptr, size, __builtin_return_address(0));
+    return ptr;
+}
+
+void *calloc(size_t n, size_t size) {
+    void *ptr = __libc_calloc(n, size);
+    note_alloc(ptr, n * size, __builtin_return_address(0));
+    return ptr;
+}
+
+void free(void *ptr) {
+    if (ptr && note_free(ptr, __builtin_return_address(0)))
+        __libc_free(ptr);
+}
+
+void *realloc(void *ptr, size_t size) {
+    void *site = __builtin_return_address(0);
+    if (!ptr) {
+        void *new = __libc_realloc(NULL, size);
+        note_alloc(new, size, site);
+        return new;
+    }
+    if (size == 0) {
+        if (note_free(ptr, site))
+            __libc_free(ptr);
+        return NULL;
+    }
+    if (!active())
+        return __libc_realloc(ptr, size);
+    pthread_mutex_lock(&lock);
+    struct slot old = table[find(ptr)];
+    pthread_mutex_unlock(&lock);
+    if (old.state == DEAD) {
+        pthread_mutex_lock(&lock);
+        note_misuse(ptr, site, old.site);
+        pthread_mutex_unlock(&lock);
+        return NULL;
+    }
+    void *new = __libc_realloc(ptr, size);
+    if (!new)
+        return NULL;
+    pthread_mutex_lock(&lock);
+    size_t i = find(ptr);
+    if (table[i].state != EMPTY)
+        erase(i);
+    /* a tracked block stays tracked, blamed on where it was first allocated */
+    if (old.state == LIVE)
+        insert(new, size, old.site, old.call);
+    else if (recording)
+        insert(new, size, site, recording);
+    else if (table[i = find(new)].state != EMPTY)
+        erase(i);
+    pthread_mutex_unlock(&lock);
+    return new;
+}
+
+/* Starts recording the allocations of the calling thread, returning the
+ * number they are recorded under. */
+unsigned long crepl_heap_begin(void) {
+    recording = __atomic_add_fetch(&calls, 1, __ATOMIC_RELAXED);
+    return recording;
+}
+
+void crepl_heap_end(void) {
+    recording = 0;
+}
+
+/* Copies up to `max` of the blocks not freed yet to `out`, returning how
+ * many there are. */
+size_t crepl_heap_live(struct crepl_block *out, size_t max) {
+    size_t n = 0;
+    pthread_mutex_lock(&lock);
+    for (size_t i = 0; i < SLOTS; i++) {
+        if (table[i].state != LIVE)
+            continue;
+        if (n < max)
+            out[n] = (struct crepl_block){table[i].ptr, table[i].size, table[i].site,
+                                          table[i].call};
+        n++;
+    }
+    pthread_mutex_unlock(&lock);
+    return n;
+}
+
+/* How many blocks were allocated during calls while the table was full,
+ * they are missing from crepl_heap_live. */
+size_t crepl_heap_dropped(void) {
+    pthread_mutex_lock(&lock);
+    size_t n = dropped;
+    pthread_mutex_unlock(&lock);
+    return n;
+}
+
+/* Copies the misuses numbered from `since` on that are still kept, at most
+ * MISUSES, to `out`, returning how many misuses there were in total. */
+size_t crepl_heap_misuses(size_t since, struct crepl_misuse *out) {
+    pthread_mutex_lock(&lock);
+    size_t total = misuse_count;
+    if (total > MISUSES && since < total - MISUSES)
+        since = total - MISUSES;
+    for (size_t i = since; i < total; i++)
+        out[i - since] = misuses[i % MISUSES];
+    pthread_mutex_unlock(&lock);
+    return total;
+}
+
+/* Stops tracking every block, releasing those held back. */
+void crepl_heap_forget(void) {
+    pthread_mutex_lock(&lock);
+    for (size_t i = 0; i < FREED; i++) {
+        if (!freed[i])
+            continue;
+        /* the dead entry goes with the block it stands for */
+        size_t j = find(freed[i]);
+        if (table[j].state == DEAD)
+            erase(j);
+        __libc_free(freed[i]);
+        freed[i] = NULL;
+    }
+    /* only live entries are left */
+    memset(table, 0, sizeof(table));
+    used = 0;
+    dropped = 0;
+    freed_next = 0;
+    pthread_mutex_unlock(&lock);
+}
@@ -0,0 +1,220 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    ops::Range,
+    time::SystemTime,
+};
+
+use logos::Logos;
+use rustyline::{
+    Context, Helper,
+    completion::Completer,
+    highlight::{CmdKind, Highlighter},
+    hint::{Hint, Hinter},
+    validate::{ValidationContext, ValidationResult, Validator},
+};
+
+use crate::{
+    command::find_command,
+    config::config,
+    elf::dynamic_symbols,
+    lex::{Token, lex_spanned},
+    proto::Protos,
+    registry::base_name,
+    session::Session,
+};
+
+const RESET: &str = "\x1b[m";
+
+/// Symbols a library file defines, read again when the file changes.
+struct LibSymbols {
+    modified: Option<SystemTime>,
+    names: HashSet<String>,
+}
+
+/// Line editor helper: colours the line as it is typed, hints the prototype
+/// of the function being called and keeps reading lines while parentheses,
+/// braces or quotes are left open.
+#[derive(Default)]
+pub struct ReplHelper {
+    protos: Protos,
+    /// Keyed by the file of each linked library
+    libs: HashMap<String, LibSymbols>,
+}
+
+/// Hint shown after the cursor, right arrow does not insert it.
+pub struct ProtoHint(String);
+
+impl Hint for ProtoHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl ReplHelper {
+    /// Catches up with the prototypes and libraries of `session`.
+    pub fn update(&mut self, session: &Session) {
+        self.protos = session.protos.clone();
+        let files = session.libs.lib_files();
+        self.libs.retain(|path, _| files.contains(path));
+        for path in files {
+            let modified = modified(&path);
+            if self
+                .libs
+                .get(&path)
+                .is_some_and(|lib| lib.modified == modified)
+            {
+                continue;
+            }
+            let names = dynamic_symbols(&path)
+                .map(|syms| syms.into_keys().collect())
+                .unwrap_or_default();
+            self.libs.insert(path, LibSymbols { modified, names });
+        }
+    }
+
+    fn is_known(&self, sym: &str) -> bool {
+        let name = base_name(sym);
+        self.protos.get(name).is_some() || self.libs.values().any(|lib| lib.names.contains(name))
+    }
+
+    fn colour(&self, token: &Token, text: &str, callee: bool) -> Option<&'static str> {
+        let theme = &config().theme;
+        let colour = match token {
+            Token::Command if find_command(text).is_some() => &theme.command,
+            Token::Command => &theme.unknown,
+            Token::Id | Token::SymRef if callee && self.is_known(text) => &theme.symbol,
+            Token::Id | Token::SymRef if callee => &theme.unknown,
+            Token::Id | Token::SymRef | Token::HistRef => &theme.identifier,
+            Token::CString | Token::CChar => &theme.string,
+            Token::CInt | Token::CFloat => &theme.number,
+            _ => return None,
+        };
+        Some(colour.as_str()).filter(|c| !c.is_empty())
+    }
+}
+
+/// Index of the token naming the called function, the first one of a call
+/// and the one after `:expect`.
+fn callee_index(tokens: &[(Token, &str)]) -> Option<usize> {
+    match tokens.first()? {
+        (Token::Id | Token::SymRef, _) => Some(0),
+        (Token::Command, ":expect") => Some(1),
+        _ => None,
+    }
+}
+
+/// Lexes `line` the way the session will, falling back to the bare lexer
+/// while the line does not lex yet (an unterminated string, ...).
+fn spanned_tokens(line: &str) -> Vec<(Token, Range<usize>)> {
+    match lex_spanned(line) {
+        Ok(tokens) => tokens
+            .into_iter()
+            .map(|((token, _), span)| (token, span))
+            .collect(),
+        Err(_) => Token::lexer(line)
+            .spanned()
+            .filter_map(|(token, span)| Some((token.ok()?, span)))
+            .collect(),
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let tokens = spanned_tokens(line);
+        let texts: Vec<(Token, &str)> = tokens
+            .iter()
+            .map(|(token, span)| (token.clone(), line.get(span.clone()).unwrap_or("")))
+            .collect();
+        let callee = callee_index(&texts);
+        let mut out = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        for (i, (token, span)) in tokens.iter().enumerate() {
+            // `Raw` and path tokens may not map back onto the line
+            let Some(text) = line.get(span.clone()).filter(|_| span.start >= last) else {
+                continue;
+            };
+            let Some(colour) = self.colour(token, text, callee == Some(i)) else {
+                continue;
+            };
+            out.push_str(&line[last..span.start]);
+            out.push_str(colour);
+            out.push_str(text);
+            out.push_str(RESET);
+            last = span.end;
+        }
+        out.push_str(&line[last..]);
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{hint}{RESET}", config().theme.hint))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        kind != CmdKind::MoveCursor
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = ProtoHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<ProtoHint> {
+        if pos < line.len() {
+            return None;
+        }
+        let tokens = lex_spanned(line).ok()?;
+        let texts: Vec<(Token, &str)> = tokens
+            .iter()
+            .map(|((token, text), _)| (token.clone(), text.as_str()))
+            .collect();
+        let callee = texts.get(callee_index(&texts)?)?.1;
+        let proto = self.protos.get(base_name(callee))?;
+        Some(ProtoHint(format!("  -- {}", proto.display(callee))))
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(match is_open(ctx.input()) {
+            true => ValidationResult::Incomplete,
+            false => ValidationResult::Valid(None),
+        })
+    }
+}
+
+/// Whether `input` leaves a quote, a parenthesis or a brace open. Closing
+/// too many is left for the parser to report.
+fn is_open(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '{') => depth += 1,
+            (None, ')' | '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    quote.is_some() || depth > 0
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+}
+
+impl Helper for ReplHelper {}
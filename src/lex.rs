@@ -1,5 +1,12 @@
 use logos::Logos;
 
+use std::ops::Range;
+
+use crate::{
+    command::{ArgSpec, find_command},
+    error::Error,
+};
+
 #[derive(Logos, Debug, Clone, PartialEq)]
 pub enum Token {
     #[regex("[a-zA-Z_][a-zA-Z0-9_]*")]
@@ -8,6 +15,13 @@ pub enum Token {
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*(\.[a-zA-Z0-9_]+)+")]
     FileName,
 
+    /// A symbol qualified with a namespace and/or a version,
+    /// `old::decode`, `memcpy@@GLIBC_2.14`
+    #[regex(
+        r"[a-zA-Z_][a-zA-Z0-9_]*::[a-zA-Z_][a-zA-Z0-9_]*(@@?[a-zA-Z0-9_.]+)?|[a-zA-Z_][a-zA-Z0-9_]*@@?[a-zA-Z0-9_.]+"
+    )]
+    SymRef,
+
     #[regex(r#""([^"\\]|\\.)*""#)]
     CString,
 
@@ -20,6 +34,10 @@ pub enum Token {
     #[regex(r"'.'")]
     CChar,
 
+    /// A previous result, `$1`, `$2`, ... or `$_` for the last one
+    #[regex(r"\$([0-9]+|_)")]
+    HistRef,
+
     #[token("(")]
     LParen,
 
@@ -54,31 +72,186 @@ pub enum Token {
     #[regex(r"[ \t\n\f]+", logos::skip)]
     WS,
 
-    #[regex(r":[rcdfvslp]|:ul|:ls|:const|:var|:t|:pa")]
+    /// Any `:name`, whether it is a registered command is up to the registry
+    #[regex(r":[a-zA-Z_][a-zA-Z0-9_]*")]
     Command,
+
+    /// Unlexed command arguments, e.g. the C source of `:c { ... }`.
+    Raw,
 }
 
-#[allow(unused)]
-pub fn lex(cmd: &str) -> Vec<(Token, String)> {
+/// A token, its text and the byte range it was lexed from.
+pub type Spanned = ((Token, String), Range<usize>);
+
+pub fn lex(cmd: &str) -> Result<Vec<(Token, String)>, Error> {
+    Ok(lex_spanned(cmd)?.into_iter().map(|(tok, _)| tok).collect())
+}
+
+pub fn lex_spanned(cmd: &str) -> Result<Vec<Spanned>, Error> {
+    let trimmed = cmd.trim_start();
+    let (head, rest) = trimmed
+        .split_once(char::is_whitespace)
+        .unwrap_or((trimmed, ""));
+    let head_at = offset_in(cmd, head);
+    let head_tok = (
+        (Token::Command, head.to_string()),
+        head_at..head_at + head.len(),
+    );
+    match find_command(head).map(|cmd| cmd.args()) {
+        Some(ArgSpec::Paths) => {
+            let mut out = vec![head_tok];
+            out.extend(lex_paths(rest, offset_in(cmd, rest)));
+            return Ok(out);
+        }
+        Some(ArgSpec::Raw) => {
+            let mut out = vec![head_tok];
+            let raw = rest.trim();
+            if !raw.is_empty() {
+                let at = offset_in(cmd, raw);
+                out.push(((Token::Raw, raw.to_string()), at..at + raw.len()));
+            }
+            return Ok(out);
+        }
+        _ => {}
+    }
+    // `:c { int add(int a, int b) { return a + b; } }`, as opposed to `:c` alone
+    if let Some(block) = trimmed
+        .strip_prefix(":c")
+        .map(str::trim)
+        .filter(|rest| rest.starts_with('{'))
+    {
+        let body = block
+            .strip_prefix('{')
+            .and_then(|b| b.strip_suffix('}'))
+            .unwrap_or(&block[1..]);
+        let at = offset_in(cmd, block);
+        return Ok(vec![
+            ((Token::Command, ":c".to_string()), head_at..head_at + 2),
+            ((Token::Raw, body.to_string()), at..at + block.len()),
+        ]);
+    }
+
     let mut lexer = Token::lexer(cmd);
     let mut out = Vec::new();
     while let Some(token) = lexer.next() {
+        let span = lexer.span();
         match token {
             Err(_) => {
-                println!("Unrecognized token!");
+                return Err(
+                    Error::lex(format!("unrecognized token `{}`", lexer.slice())).with_span(span),
+                );
             }
             Ok(Token::WS) => {}
             Ok(Token::CString) => {
-                out.push((Token::CString, lexer.slice().trim_matches('"').to_string()));
+                out.push((
+                    (Token::CString, lexer.slice().trim_matches('"').to_string()),
+                    span,
+                ));
             }
             Ok(Token::CChar) => out.push((
-                Token::CChar,
-                lexer.slice().chars().nth(1).unwrap().to_string(),
+                (
+                    Token::CChar,
+                    lexer.slice().chars().nth(1).unwrap().to_string(),
+                ),
+                span,
             )),
             Ok(tok) => {
-                out.push((tok, lexer.slice().to_string()));
+                out.push(((tok, lexer.slice().to_string()), span));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Byte offset of `sub`, a slice of `s`, in `s`. Empty slices that are not
+/// part of `s` are at its end.
+fn offset_in(s: &str, sub: &str) -> usize {
+    (sub.as_ptr() as usize)
+        .wrapping_sub(s.as_ptr() as usize)
+        .min(s.len())
+}
+
+/// Splits on whitespace, honoring single and double quotes, so that paths like
+/// `./build/libfoo.so`, `/opt/lib/libbar.so.1` or `"my libs/libbaz.so"` come
+/// through as a single `FileName`. `at` is the offset of `args` in the line.
+fn lex_paths(args: &str, at: usize) -> Vec<Spanned> {
+    let mut out = Vec::new();
+    let mut chars = args.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        let mut end = args.len();
+        if c == '"' || c == '\'' {
+            chars.next();
+            while let Some((i, ch)) = chars.next() {
+                match ch {
+                    '\\' => word.extend(chars.next().map(|(_, ch)| ch)),
+                    ch if ch == c => {
+                        end = i + 1;
+                        break;
+                    }
+                    ch => word.push(ch),
+                }
+            }
+        } else {
+            while let Some(&(i, ch)) = chars.peek() {
+                if ch.is_whitespace() {
+                    end = i;
+                    break;
+                }
+                word.push(ch);
+                chars.next();
             }
         }
+        out.push(((Token::FileName, word), at + start..at + end));
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(args: &str) -> Vec<String> {
+        lex_paths(args, 0)
+            .into_iter()
+            .map(|((_, w), _)| w)
+            .collect()
+    }
+
+    #[test]
+    fn paths_split_on_whitespace() {
+        assert_eq!(
+            words("./build/libfoo.so   /opt/lib/libbar.so.1"),
+            ["./build/libfoo.so", "/opt/lib/libbar.so.1"]
+        );
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn quoted_paths_keep_spaces_and_escapes() {
+        assert_eq!(
+            words(r#""my libs/libbaz.so" 'it''s' "a\"b""#),
+            ["my libs/libbaz.so", "it", "s", "a\"b"]
+        );
+    }
+
+    #[test]
+    fn path_spans_are_offset_into_the_line() {
+        let spans: Vec<_> = lex_paths("a \"b c\"", 4)
+            .into_iter()
+            .map(|(_, span)| span)
+            .collect();
+        assert_eq!(spans, [4..5, 6..11]);
+    }
+
+    #[test]
+    fn unterminated_quote_runs_to_the_end() {
+        let out = lex_paths("\"abc", 0);
+        assert_eq!(out[0].0.1, "abc");
+        assert_eq!(out[0].1, 0..4);
+    }
+}
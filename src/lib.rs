@@ -1,8 +1,28 @@
+#![allow(non_snake_case)]
+pub mod bench;
+pub mod call;
 pub mod cffi;
 pub mod cli;
+pub mod command;
+pub mod compile;
+pub mod config;
 pub mod dlfcn;
+pub mod elf;
+pub mod error;
 pub mod eval;
+pub mod heap;
+pub mod helper;
 pub mod lex;
+pub mod libpath;
+pub mod mem;
+pub mod owned;
 pub mod parser;
+pub mod proto;
+pub mod rc;
 pub mod registry;
+pub mod report;
+pub mod session;
+pub mod snapshot;
+pub mod trace;
 pub mod vars;
+pub mod watch;
@@ -0,0 +1,190 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::error::Error;
+
+static SEARCH_PATHS: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+
+const STANDARD_DIRS: &[&str] = &["/lib", "/usr/lib", "/lib64", "/usr/lib64", "/usr/local/lib"];
+
+#[cfg(target_arch = "x86_64")]
+const LDCONFIG_ARCH: Option<&str> = Some("x86-64");
+#[cfg(target_arch = "aarch64")]
+const LDCONFIG_ARCH: Option<&str> = Some("AArch64");
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const LDCONFIG_ARCH: Option<&str> = None;
+
+fn search_paths() -> &'static Mutex<Vec<String>> {
+    SEARCH_PATHS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+pub fn add_search_path(dir: &str) -> Result<(), Error> {
+    let dir = expand_home(dir);
+    if !Path::new(&dir).is_dir() {
+        return Err(Error::link(format!("`{dir}` is not a directory")));
+    }
+    let mut paths = search_paths().lock().unwrap();
+    if !paths.contains(&dir) {
+        paths.push(dir);
+    }
+    Ok(())
+}
+
+pub fn del_search_path(dir: &str) -> Result<(), Error> {
+    let dir = expand_home(dir);
+    let mut paths = search_paths().lock().unwrap();
+    let before = paths.len();
+    paths.retain(|p| *p != dir);
+    if paths.len() == before {
+        return Err(Error::link(format!(
+            "`{dir}` is not in the library search path"
+        )));
+    }
+    Ok(())
+}
+
+/// Directories added with `:libpath add`.
+pub fn added_search_paths() -> Vec<String> {
+    search_paths().lock().unwrap().clone()
+}
+
+/// User-added directories first, then the ones from `LD_LIBRARY_PATH`.
+pub fn get_search_paths() -> Vec<String> {
+    let mut out = search_paths().lock().unwrap().clone();
+    out.extend(ld_library_path());
+    out
+}
+
+pub fn display_search_paths() {
+    println!("INFO: Library search path: ");
+    for dir in search_paths().lock().unwrap().iter() {
+        println!("\t- {dir}");
+    }
+    for dir in ld_library_path() {
+        println!("\t- {dir} (LD_LIBRARY_PATH)");
+    }
+}
+
+fn ld_library_path() -> Vec<String> {
+    env::var("LD_LIBRARY_PATH")
+        .map(|v| {
+            v.split(':')
+                .filter(|d| !d.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `~/lib` -> `$HOME/lib`
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+        _ => path.to_string(),
+    }
+}
+
+/// Turns what the user typed after `:l` into something `dlopen` can open.
+///
+/// - anything containing a `/` is a path and is used as is,
+/// - `libfoo.so`-like file names are looked up in the search path, falling
+///   back to `dlopen`'s own lookup,
+/// - bare names (`m`, `libm`, `-lm`) are resolved the way the linker would,
+///   through the search path, `ldconfig -p` and the standard directories.
+pub fn resolve_lib(name: &str) -> Result<String, Error> {
+    let name = expand_home(name);
+    if name.contains('/') {
+        return Ok(name);
+    }
+    if name.contains(".so") {
+        let found = get_search_paths()
+            .iter()
+            .map(|dir| Path::new(dir).join(&name))
+            .find(|p| p.is_file());
+        return Ok(found.map(|p| p.display().to_string()).unwrap_or(name));
+    }
+
+    let stem = name.strip_prefix("-l").unwrap_or(&name);
+    let stem = stem.strip_prefix("lib").unwrap_or(stem);
+    if stem.is_empty() {
+        return Err(Error::link(format!("`{name}` is not a valid library name")));
+    }
+
+    get_search_paths()
+        .iter()
+        .find_map(|dir| find_in_dir(Path::new(dir), stem))
+        .or_else(|| find_in_ldconfig(stem))
+        .or_else(|| {
+            standard_dirs()
+                .iter()
+                .find_map(|dir| find_in_dir(dir, stem))
+        })
+        .map(|p| p.display().to_string())
+        .ok_or_else(|| {
+            Error::link(format!(
+                "Could not find a shared object for `{name}` (looked for lib{stem}.so*)"
+            ))
+        })
+}
+
+fn standard_dirs() -> Vec<PathBuf> {
+    let multiarch = format!("{}-linux-gnu", env::consts::ARCH);
+    let mut dirs: Vec<PathBuf> = STANDARD_DIRS.iter().map(PathBuf::from).collect();
+    dirs.push(Path::new("/lib").join(&multiarch));
+    dirs.push(Path::new("/usr/lib").join(&multiarch));
+    dirs
+}
+
+/// Picks `libstem.so` if it is a real ELF object (it is often a linker script),
+/// otherwise the shortest versioned `libstem.so.N...`.
+fn find_in_dir(dir: &Path, stem: &str) -> Option<PathBuf> {
+    let plain = format!("lib{stem}.so");
+    let versioned = format!("lib{stem}.so.");
+    let mut candidates: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let file = e.file_name();
+            let file = file.to_string_lossy();
+            file == plain || file.starts_with(&versioned)
+        })
+        .map(|e| e.path())
+        .filter(|p| is_elf(p))
+        .collect();
+    candidates.sort_by_key(|p| p.as_os_str().len());
+    candidates.into_iter().next()
+}
+
+fn find_in_ldconfig(stem: &str) -> Option<PathBuf> {
+    let output = Command::new("ldconfig").arg("-p").output().ok()?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let plain = format!("lib{stem}.so");
+    let versioned = format!("lib{stem}.so.");
+    // lines look like `\tlibm.so.6 (libc6,x86-64) => /lib/x86_64-linux-gnu/libm.so.6`
+    listing
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let (lhs, path) = line.split_once(" => ")?;
+            let (soname, flags) = lhs.trim().split_once(' ')?;
+            Some((soname, flags, path.trim()))
+        })
+        .filter(|(soname, _, _)| *soname == plain || soname.starts_with(&versioned))
+        .filter(|(_, flags, _)| LDCONFIG_ARCH.is_none_or(|arch| flags.contains(arch)))
+        .map(|(_, _, path)| PathBuf::from(path))
+        .find(|p| is_elf(p))
+}
+
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| magic == *b"\x7fELF")
+        .unwrap_or(false)
+}
@@ -1,280 +1,498 @@
-use crate::cffi::{CallInterface, FfiType};
+#![allow(non_snake_case)]
 use std::{
-    error::Error,
-    ffi::{CStr, c_void},
+    ffi::CStr,
+    io::{IsTerminal, Write},
+    path::Path,
+    process::ExitCode,
 };
 
 use CREPLrs::{
-    cffi::{self},
-    cli::{Cli, OpMode},
-    eval::Value,
-    lex::Token,
-    registry::{add_lib, del_lib, get_libs, get_sym},
-    vars::{const_eval, display_all, display_vars, get_value, set_value, var_eval},
+    cli::Cli,
+    config::{apply_startup, config, init_config},
+    error::Error,
+    eval::show_result,
+    heap,
+    rc::{is_trusted, rc_files, trust},
+    report::{Check, ReportFormat, ScriptReport, print_report},
+    session::{Output, Session},
 };
 
-use libc::{FILE, c_char};
+use libc::FILE;
 unsafe extern "C" {
     static mut stdout: *mut FILE;
     fn setvbuf(stream: *mut FILE, buf: *mut libc::c_char, mode: i32, size: usize) -> i32;
 }
 
 const RED: &str = "\x1b[31m";
-const BLUE: &str = "\x1b[34m";
 const RESET: &str = "\x1b[m";
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut mode = OpMode::Void;
-    let mut last = "0".to_string();
-    let mut cli = Cli::new(&mode);
+const USAGE: &str =
+    "usage: CREPLrs [--keep-going] [--norc] [--heapcheck] [--session <file> [--autosave]] [-e <command>]... [script | -]
+       CREPLrs test [--tap | --junit] <dir | script>...";
+
+/// Command line, without a script or `-e` commands (and with a terminal on
+/// stdin) the REPL is interactive.
+struct Args {
+    script: Option<String>,
+    commands: Vec<String>,
+    keep_going: bool,
+    /// Loaded before anything else runs, when it exists
+    session: Option<String>,
+    /// Save the session back to `session` on exit
+    autosave: bool,
+    /// Skip `~/.creplrc` and `./.creplrc`
+    norc: bool,
+    /// Record what calls allocate, see `:leaks`
+    heapcheck: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        script: None,
+        commands: Vec::new(),
+        keep_going: false,
+        session: None,
+        autosave: false,
+        norc: false,
+        heapcheck: false,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-e" => match argv.next() {
+                Some(cmd) => args.commands.push(cmd),
+                None => return Err("`-e` expects a command".to_string()),
+            },
+            "--keep-going" => args.keep_going = true,
+            "--session" => match argv.next() {
+                Some(file) => args.session = Some(file),
+                None => return Err("`--session` expects a file".to_string()),
+            },
+            "--autosave" => args.autosave = true,
+            "--norc" => args.norc = true,
+            "--heapcheck" => args.heapcheck = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option `{arg}`"));
+            }
+            _ if args.script.is_some() => return Err("only one script can be run".to_string()),
+            _ => args.script = Some(arg),
+        }
+    }
+    if args.autosave && args.session.is_none() {
+        return Err("`--autosave` needs a `--session` file to save to".to_string());
+    }
+    Ok(args)
+}
+
+fn main() -> ExitCode {
+    if std::env::args().nth(1).as_deref() == Some("test") {
+        return run_tests(std::env::args().skip(2).collect());
+    }
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{RED}ERROR: {e}{RESET}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    if args.heapcheck && !heap::enabled() {
+        eprintln!("{RED}{}{RESET}", heap::reexec_with_shim());
+        return ExitCode::FAILURE;
+    }
+    heap::settle();
     unsafe {
         setvbuf(stdout, std::ptr::null_mut(), libc::_IONBF, 0);
     }
-    while let Some(tokens) = cli.next() {
-        if tokens.is_empty() {
-            continue;
+    if let Err(e) = init_config() {
+        eprintln!("{RED}{e}{RESET}");
+    }
+    let mut session = Session::new();
+    for e in apply_startup(config(), &mut session) {
+        eprintln!("{RED}{e}{RESET}");
+    }
+    if !args.norc {
+        run_rc_files(&mut session);
+    }
+    session.mark_startup();
+    if let Some(file) = args.session.as_deref().filter(|f| Path::new(f).exists())
+        && let Err(e) = session.load(file)
+    {
+        eprintln!("{RED}{e}{RESET}");
+        return ExitCode::FAILURE;
+    }
+    let ok = if args.script.is_none() && args.commands.is_empty() && std::io::stdin().is_terminal()
+    {
+        repl(&mut session);
+        true
+    } else {
+        run_non_interactive(&args, &mut session)
+    };
+    if let Some(file) = args.session.as_deref().filter(|_| args.autosave) {
+        match session.save(file) {
+            Ok(()) => println!("INFO: session saved to {file}"),
+            Err(e) => eprintln!("{RED}{e}{RESET}"),
         }
-        if tokens[0].1 == ":r" {
-            match tokens.len() {
-                1 => {
-                    println!("{last}");
-                    continue;
-                }
-                2 => {
-                    let tmp = match mode {
-                        OpMode::Int => Value::Integer(last.parse::<i64>().unwrap()),
-                        OpMode::Float => Value::Number(last.parse::<f64>().unwrap()),
-                        OpMode::Ptr => Value::CString(last.clone()),
-                        OpMode::Char => Value::CChar(last.chars().nth(0).unwrap()),
-                        _ => {
-                            eprintln!(
-                                "{RED} switch operation mode before assigning the last result to a variable, attempt of assigning () to a variable detected ...{RESET}"
-                            );
-                            continue;
-                        }
-                    };
-                    set_value(&tokens[1].1, tmp);
-                    continue;
-                }
-                _ => {
-                    eprintln!("{RED}ERROR: Syntax Error: expected Syntax is `:r <var>` or `:r`");
-                    continue;
-                }
+    }
+    print_warnings(session.close());
+    match ok {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+/// Runs `~/.creplrc`, then `./.creplrc` once the user trusts it, through
+/// the same dispatcher as the prompt. Failing lines are reported and skipped.
+fn run_rc_files(session: &mut Session) {
+    let (user, project) = rc_files();
+    if let Some(path) = user {
+        match std::fs::read_to_string(&path) {
+            Ok(src) => {
+                run_batch(&path.display().to_string(), src.lines(), session, true);
             }
+            Err(e) => eprintln!("{RED}Could not read {}: {e}{RESET}", path.display()),
         }
-        if tokens[0].1 == ":d" {
-            mode = OpMode::Int;
-            cli.update_mode(&mode);
-            continue;
-        }
-        if tokens[0].1 == ":f" {
-            mode = OpMode::Float;
-            cli.update_mode(&mode);
-            continue;
-        }
-        if tokens[0].1 == ":c" {
-            mode = OpMode::Char;
-            cli.update_mode(&mode);
-            continue;
+    }
+    let Some(path) = project else {
+        return;
+    };
+    let src = match std::fs::read_to_string(&path) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("{RED}Could not read {}: {e}{RESET}", path.display());
+            return;
         }
-        if tokens[0].1 == ":v" {
-            mode = OpMode::Void;
-            cli.update_mode(&mode);
-            continue;
+    };
+    if !is_trusted(&path, &src) && !ask_trust(&path, &src) {
+        return;
+    }
+    run_batch(&path.display().to_string(), src.lines(), session, true);
+}
+
+/// Asks whether to run a project rc that is new or changed since it was last
+/// trusted, showing it first. Without a terminal to ask on it is skipped.
+fn ask_trust(path: &Path, src: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "{RED}WARNING: skipping {}, it is not trusted yet, start CREPLrs interactively to review it{RESET}",
+            path.display()
+        );
+        return false;
+    }
+    eprintln!(
+        "{RED}WARNING: {} is new or changed since you last trusted it, it can load and run arbitrary code:{RESET}",
+        path.display()
+    );
+    for line in src.lines() {
+        eprintln!("\t{line}");
+    }
+    eprint!("Run it? [y/N] ");
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err()
+        || !matches!(answer.trim(), "y" | "Y" | "yes")
+    {
+        return false;
+    }
+    if let Err(e) = trust(path, src) {
+        eprintln!("{RED}{e}{RESET}");
+    }
+    true
+}
+
+/// Runs the `-e` commands, then the script or what is piped on stdin.
+/// Returns whether everything succeeded.
+fn run_non_interactive(args: &Args, session: &mut Session) -> bool {
+    // `-e` commands run before the script
+    let mut ok = run_batch(
+        "-e",
+        args.commands.iter().map(String::as_str),
+        session,
+        args.keep_going,
+    );
+    let script = match args.script.clone() {
+        Some(path) if path != "-" => {
+            let src = std::fs::read_to_string(&path);
+            Some((path, src))
         }
-        if tokens[0].1 == ":s" {
-            mode = OpMode::Ptr;
-            cli.update_mode(&mode);
-            continue;
+        Some(_) => Some((
+            "<stdin>".to_string(),
+            std::io::read_to_string(std::io::stdin()),
+        )),
+        None if args.commands.is_empty() => Some((
+            "<stdin>".to_string(),
+            std::io::read_to_string(std::io::stdin()),
+        )),
+        None => None,
+    };
+    if let Some((name, src)) = script
+        && (ok || args.keep_going)
+    {
+        match src {
+            Ok(src) => ok &= run_batch(&name, src.lines(), session, args.keep_going),
+            Err(e) => {
+                eprintln!("{RED}ERROR: Could not read {name}: {e}{RESET}");
+                ok = false;
+            }
         }
-        if tokens[0].1 == ":l" {
-            tokens.iter().skip(1).for_each(|tok| match tok.0 {
-                Token::FileName => add_lib(&tok.1),
-                _ => eprintln!("{RED}`{}` is not a valid file name!{RESET}", tok.1),
-            });
-            continue;
+    }
+    ok
+}
+
+fn repl(session: &mut Session) {
+    let mut cli = Cli::new(session);
+    while let Some(line) = cli.next() {
+        print_reload_errors(session);
+        let res = session.eval_line(&line);
+        print_pending(session);
+        match res {
+            Ok(output) => print_output(&output),
+            Err(e) => print_error(None, &line, &e),
         }
-        if tokens[0].1 == ":ul" {
-            tokens.iter().skip(1).for_each(|tok| del_lib(&tok.1));
-            continue;
+        cli.update(session);
+    }
+}
+
+/// Prints `e`, prefixed with `location` (`name:line`) when there is one, and
+/// underlines the part of `line` it blames.
+fn print_error(location: Option<(&str, usize)>, line: &str, e: &Error) {
+    let red = &config().theme.error;
+    let span = e
+        .span
+        .clone()
+        .filter(|span| span.start <= span.end && line.get(span.clone()).is_some());
+    match (location, &span) {
+        (Some((name, n)), Some(span)) => {
+            let col = line[..span.start].chars().count() + 1;
+            eprintln!("{red}{name}:{n}:{col}: {e}{RESET}");
         }
-        if tokens[0].1 == ":ls" {
-            get_libs();
+        (Some((name, n)), None) => eprintln!("{red}{name}:{n}: {e}{RESET}"),
+        (None, _) => eprintln!("{red}{e}{RESET}"),
+    }
+    if let Some(span) = span {
+        // input continued over several lines is underlined on the one the
+        // span starts on
+        let start = line[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let end = line[span.start..]
+            .find('\n')
+            .map_or(line.len(), |i| span.start + i);
+        let pad: String = line[start..span.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = line[span.start..span.end.min(end)].chars().count().max(1);
+        eprintln!(
+            "\t{}\n\t{pad}{red}{}{RESET}",
+            &line[start..end],
+            "^".repeat(width)
+        );
+    }
+}
+
+/// Reports the watched libraries that could not be reloaded and the pointers
+/// a reload left dangling.
+fn print_reload_errors(session: &mut Session) {
+    let red = &config().theme.error;
+    for e in session.reload_watched() {
+        eprintln!("{red}{e}{RESET}");
+    }
+}
+
+/// Prints the calls traced and the warnings of the lines evaluated since the
+/// last time.
+fn print_pending(session: &mut Session) {
+    for trace in session.trace.take_recorded() {
+        eprint!("{}", trace.render());
+    }
+    print_warnings(session.take_warnings());
+}
+
+fn print_warnings(warnings: Vec<Error>) {
+    let red = &config().theme.error;
+    for e in warnings {
+        eprintln!("{red}WARNING: {}{RESET}", e.msg);
+    }
+}
+
+fn print_output(output: &Output) {
+    let Output::Call(call) = output else {
+        return;
+    };
+    let theme = &config().theme;
+    if let (Some(n), Some(value)) = (call.index, &call.value) {
+        println!(
+            "\n{}${n} = {}{RESET}",
+            theme.result,
+            show_result(call.ty, value)
+        );
+    }
+    if let Some(errno) = call.errno.filter(|errno| *errno != 0) {
+        let msg = unsafe { CStr::from_ptr(libc::strerror(errno)) };
+        eprintln!(
+            "{}errno = {errno} ({}){RESET}",
+            theme.error,
+            msg.to_string_lossy()
+        );
+    }
+}
+
+/// Runs `lines` one after the other, reporting errors as `name:line:`.
+/// Blank lines and `#` comments are skipped. Returns whether every line
+/// succeeded, stopping at the first failure unless `keep_going`.
+fn run_batch<'a>(
+    name: &str,
+    lines: impl Iterator<Item = &'a str>,
+    session: &mut Session,
+    keep_going: bool,
+) -> bool {
+    let mut ok = true;
+    for (i, line) in lines.enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
         }
-        if tokens[0].1 == ":const" {
-            println!("{:?}", const_eval(tokens.into_iter().skip(1).collect()));
-            continue;
+        print_reload_errors(session);
+        let res = session.eval_line(line);
+        print_pending(session);
+        match res {
+            Ok(output) => print_output(&output),
+            Err(e) => {
+                print_error(Some((name, i + 1)), line, &e);
+                ok = false;
+                if !keep_going {
+                    break;
+                }
+            }
         }
+    }
+    ok
+}
 
-        if tokens[0].1 == ":var" {
-            println!("{:?}", var_eval(tokens.into_iter().skip(1).collect()));
-            continue;
+/// `CREPLrs test [--tap | --junit] <dir | script>...`, runs every `.crepl`
+/// script in a session of its own and reports its `:assert`/`:expect` lines.
+fn run_tests(args: Vec<String>) -> ExitCode {
+    let mut format = ReportFormat::Human;
+    let mut scripts = Vec::new();
+    for arg in &args {
+        match arg.as_str() {
+            "--tap" => format = ReportFormat::Tap,
+            "--junit" => format = ReportFormat::Junit,
+            _ if arg.starts_with('-') => {
+                eprintln!("{RED}ERROR: unknown option `{arg}`{RESET}\n{USAGE}");
+                return ExitCode::from(2);
+            }
+            _ => {
+                if let Err(e) = collect_scripts(Path::new(arg), &mut scripts) {
+                    eprintln!("{RED}ERROR: {e}{RESET}");
+                    return ExitCode::from(2);
+                }
+            }
         }
+    }
+    if scripts.is_empty() {
+        eprintln!("{RED}ERROR: no `.crepl` scripts to run{RESET}\n{USAGE}");
+        return ExitCode::from(2);
+    }
+    // results are printed in the configured theme, startup libraries are
+    // left out for the scripts to run the same everywhere
+    if let Err(e) = init_config() {
+        eprintln!("{RED}{e}{RESET}");
+    }
 
-        if tokens[0].1 == ":p" {
-            display_vars(tokens.into_iter().skip(1).collect());
-            continue;
+    // what the scripts print goes to stderr, keeping stdout for the report.
+    // C stdout is unbuffered first, nothing the called functions print can
+    // then be left to end up in the report
+    let saved_stdout = unsafe {
+        setvbuf(stdout, std::ptr::null_mut(), libc::_IONBF, 0);
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO);
+        fd
+    };
+    let reports: Vec<ScriptReport> = scripts.iter().map(|path| test_script(path)).collect();
+    let _ = std::io::stdout().flush();
+    unsafe {
+        libc::dup2(saved_stdout, libc::STDOUT_FILENO);
+        libc::close(saved_stdout);
+    }
+
+    print_report(&reports, format);
+    match reports.iter().all(|r| r.failures() == 0) {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+/// `path` itself, or the `.crepl` files under it, sorted.
+fn collect_scripts(path: &Path, out: &mut Vec<String>) -> Result<(), String> {
+    if !path.is_dir() {
+        if !path.exists() {
+            return Err(format!("{} does not exist", path.display()));
         }
+        out.push(path.display().to_string());
+        return Ok(());
+    }
+    let mut entries: Vec<_> = std::fs::read_dir(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "crepl") {
+            collect_scripts(&entry, out)?;
+        }
+    }
+    Ok(())
+}
 
-        if tokens[0].1 == ":pa" {
-            display_all();
-            continue;
+/// Runs one test script, recording every check. A failing command that is
+/// not a check aborts the script and is reported as a failure.
+fn test_script(path: &str) -> ScriptReport {
+    let mut session = Session::new();
+    let mut checks = Vec::new();
+    let src = match std::fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(e) => {
+            checks.push(Check {
+                line: 0,
+                command: String::new(),
+                failure: Some(format!("Could not read {path}: {e}")),
+            });
+            return ScriptReport {
+                path: path.to_string(),
+                checks,
+            };
         }
-        if tokens[0].0 != Token::Id {
-            eprintln!("{RED}ERROR: Expected a function as the first lexeme{RESET}");
+    };
+    for (i, line) in src.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
         }
-        let maybe_called_fn = get_sym(&tokens[0].1);
-        #[allow(clippy::needless_late_init)]
-        let called_fn;
-        match maybe_called_fn {
-            Some(f) => {
-                called_fn = f;
-            }
-            None => {
-                continue;
-            }
+        let is_check = matches!(
+            trimmed.split_whitespace().next(),
+            Some(":assert" | ":expect")
+        );
+        let res = session.eval_line(line);
+        print_pending(&mut session);
+        if let Ok(output) = &res {
+            print_output(output);
         }
-        let mut cif_arg_types = Vec::new();
-        let mut cif_args = Vec::new();
-        let mut arg_boxes: Vec<Box<dyn std::any::Any>> = Vec::new();
-        for token in tokens.iter().skip(1) {
-            cif_arg_types.push(match token.0 {
-                Token::CString => FfiType::Pointer,
-                Token::CInt => FfiType::SInt64,
-                Token::CFloat => FfiType::Double,
-                Token::CChar => FfiType::SInt8,
-                Token::Id => match get_value(&token.1) {
-                    None => {
-                        eprintln!(
-                            "{RED}ERROR: variable or constant `{}` does not exist{RESET}",
-                            token.1
-                        );
-                        continue;
-                    }
-                    Some(Value::CString(_)) => FfiType::Pointer,
-                    Some(Value::CChar(_)) => FfiType::SInt8,
-                    Some(Value::Integer(_)) => FfiType::SInt64,
-                    Some(Value::Number(_)) => FfiType::Double,
-                    Some(Value::Bool(_)) => FfiType::SInt8,
-                },
-                _ => FfiType::Void,
+        if is_check || res.is_err() {
+            checks.push(Check {
+                line: i + 1,
+                command: trimmed.to_string(),
+                failure: res.as_ref().err().map(|e| e.to_string()),
             });
-            match token.0 {
-                Token::CString => {
-                    let cstr = Box::new(std::ffi::CString::new(token.1.clone()).unwrap());
-                    let char_ptr = cstr.as_ptr();
-                    arg_boxes.push(cstr);
-
-                    let ptr_box = Box::new(char_ptr);
-                    cif_args.push(&*ptr_box as *const _ as *mut c_void);
-                    arg_boxes.push(ptr_box);
-                }
-                Token::CInt => {
-                    let val = Box::new(token.1.parse::<i64>().unwrap());
-                    cif_args.push(&*val as *const _ as *mut c_void);
-                    arg_boxes.push(val);
-                }
-                Token::CFloat => {
-                    let val = Box::new(token.1.parse::<f64>().unwrap());
-                    cif_args.push(&*val as *const _ as *mut c_void);
-                    arg_boxes.push(val);
-                }
-                Token::CChar => {
-                    let ch = token.1.as_bytes()[0] as i8;
-                    let val = Box::new(ch);
-                    cif_args.push(&*val as *const _ as *mut c_void);
-                    arg_boxes.push(val);
-                }
-                Token::Id => match get_value(&token.1) {
-                    None => {
-                        eprintln!(
-                            "{RED}ERROR: variable or constant `{}` does not exist{RESET}",
-                            token.1
-                        );
-                        continue;
-                    }
-                    Some(Value::CString(s)) => {
-                        println!("LOG: Got this value {s} from the variable {}", token.1);
-                        let cstr = Box::new(std::ffi::CString::new(s.clone()).unwrap());
-                        let char_ptr = cstr.as_ptr();
-                        arg_boxes.push(cstr);
-
-                        let ptr_box = Box::new(char_ptr);
-                        cif_args.push(&*ptr_box as *const _ as *mut c_void);
-                        arg_boxes.push(ptr_box);
-                    }
-                    Some(Value::CChar(c)) => {
-                        println!("LOG: Got this value {c} from the variable {}", token.1);
-                        let val = Box::new(c);
-                        cif_args.push(&*val as *const _ as *mut c_void);
-                        arg_boxes.push(val);
-                    }
-                    Some(Value::Integer(int)) => {
-                        println!("LOG: Got this value {int} from the variable {}", token.1);
-                        let val = Box::new(int);
-                        cif_args.push(&*val as *const _ as *mut c_void);
-                        arg_boxes.push(val);
-                    }
-                    Some(Value::Number(float)) => {
-                        println!("LOG: Got this value {float} from the variable {}", token.1);
-                        let val = Box::new(float);
-                        cif_args.push(&*val as *const _ as *mut c_void);
-                        arg_boxes.push(val);
-                    }
-                    Some(Value::Bool(bool)) => {
-                        let val = Box::new(bool as i8);
-                        cif_args.push(&*val as *const _ as *mut c_void);
-                        arg_boxes.push(val);
-                    }
-                    _ => {
-                        unreachable!()
-                    }
-                },
-                _ => {
-                    unreachable!()
-                }
-            }
         }
-        match mode {
-            OpMode::Int => {
-                let mut cif = CallInterface::<i64>::new(cif_arg_types)?;
-                let res = cif.call(called_fn, &cif_args);
-                last = format!("{}", res);
-                println!("\n{BLUE}{last}{RESET}");
-            }
-            OpMode::Void => {
-                let mut cif = CallInterface::<()>::new(cif_arg_types)?;
-                cif.call(called_fn, &cif_args);
-                last = format!("()");
-            }
-            OpMode::Float => {
-                let mut cif = CallInterface::<f64>::new(cif_arg_types)?;
-                let res = cif.call(called_fn, &cif_args);
-                last = format!("{}", res);
-                println!("\n{BLUE}{last}{RESET}");
-            }
-            OpMode::Ptr => {
-                let mut cif = CallInterface::<*const c_char>::new(cif_arg_types)?;
-                let res = cif.call(called_fn, &cif_args);
-                if res.is_null() {
-                    last = "(NullString)".to_string();
-                    println!("\n{BLUE}{last}{RESET}");
-                } else {
-                    unsafe {
-                        last = format!("{}", CStr::from_ptr(res).to_str().unwrap());
-                        println!("\n{BLUE}{last}{RESET}");
-                    }
-                }
-            }
-            OpMode::Char => {
-                let mut cif = CallInterface::<i8>::new(cif_arg_types)?;
-                let res = cif.call(called_fn, &cif_args);
-                last = format!("{}", res);
-                println!("\n{BLUE}{last}{RESET}");
-            }
+        if res.is_err() && !is_check {
+            break;
         }
     }
-    Ok(())
+    print_warnings(session.close());
+    ScriptReport {
+        path: path.to_string(),
+        checks,
+    }
 }
@@ -0,0 +1,150 @@
+use std::ffi::c_void;
+
+/// Bytes of a string shown next to a `char*` result.
+pub const PREVIEW_LEN: usize = 64;
+
+/// How `:str` turns bytes into text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// Invalid sequences become U+FFFD
+    Utf8,
+    /// One char per byte
+    Latin1,
+    /// Printable ASCII as is, any other byte as `\xNN`
+    Hex,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "hex" => Some(Encoding::Hex),
+            _ => None,
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|b| *b as char).collect(),
+            Encoding::Hex => bytes
+                .iter()
+                .map(|b| match b {
+                    b' '..=b'~' if *b != b'\\' => (*b as char).to_string(),
+                    b => format!("\\x{b:02x}"),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// Copies `buf.len()` bytes at `addr`, which must not cross a page, through
+/// the kernel so that unmapped memory fails instead of faulting.
+fn read_within_page(addr: usize, buf: &mut [u8]) -> bool {
+    let local = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: addr as *mut c_void,
+        iov_len: buf.len(),
+    };
+    let read = unsafe { libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0) };
+    read == buf.len() as isize
+}
+
+/// Reads up to `len` bytes at `addr`, fewer when the memory stops being
+/// readable, calling `done` on what was read so far after each page to stop
+/// early.
+fn read_pages(addr: usize, len: usize, done: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let page = page_size();
+    let mut out = Vec::new();
+    let mut at = addr;
+    while out.len() < len {
+        let chunk = (page - at % page).min(len - out.len());
+        let start = out.len();
+        out.resize(start + chunk, 0);
+        if !read_within_page(at, &mut out[start..]) {
+            out.truncate(start);
+            break;
+        }
+        if done(&out[start..]) {
+            break;
+        }
+        at += chunk;
+    }
+    out
+}
+
+/// Reads up to `len` bytes at `addr`, fewer when the memory stops being
+/// readable.
+pub fn read_memory(addr: usize, len: usize) -> Vec<u8> {
+    if addr == 0 {
+        return Vec::new();
+    }
+    read_pages(addr, len, |_| false)
+}
+
+/// The NUL terminated string at `addr`, at most `max` bytes of it, along
+/// with whether it went on. `None` when `addr` can not be read at all.
+pub fn read_c_string(addr: usize, max: usize) -> Option<(Vec<u8>, bool)> {
+    if addr == 0 {
+        return None;
+    }
+    // one more byte tells a string of exactly `max` bytes from a longer one
+    let mut bytes = read_pages(addr, max + 1, |chunk| chunk.contains(&0));
+    if bytes.is_empty() {
+        return None;
+    }
+    match bytes.iter().position(|b| *b == 0) {
+        Some(nul) => {
+            bytes.truncate(nul);
+            Some((bytes, false))
+        }
+        None => {
+            bytes.truncate(max);
+            Some((bytes, true))
+        }
+    }
+}
+
+/// A short, lossy rendering of the string at `addr`, `None` when it can not
+/// be read.
+pub fn preview(addr: usize) -> Option<String> {
+    let (bytes, truncated) = read_c_string(addr, PREVIEW_LEN)?;
+    let text = format!("{:?}", Encoding::Utf8.decode(&bytes));
+    Some(match truncated {
+        true => format!("{text}..."),
+        false => text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_bytes() {
+        let bytes = b"caf\xc3\xa9 \xff\\\n";
+        assert_eq!(Encoding::Utf8.decode(bytes), "café \u{fffd}\\\n");
+        assert_eq!(Encoding::Latin1.decode(bytes), "cafÃ© ÿ\\\n");
+        assert_eq!(Encoding::Hex.decode(bytes), "caf\\xc3\\xa9 \\xff\\x5c\\x0a");
+        assert_eq!(Encoding::Hex.decode(b""), "");
+    }
+
+    #[test]
+    fn parses_encoding_names() {
+        assert_eq!(Encoding::parse("UTF-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::parse("iso-8859-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::parse("hex"), Some(Encoding::Hex));
+        assert_eq!(Encoding::parse("ebcdic"), None);
+    }
+}
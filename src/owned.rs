@@ -0,0 +1,86 @@
+use crate::eval::{Env, Value};
+
+/// Memory a call handed over to the REPL, see `owned(...)` in `:proto`.
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub addr: usize,
+    /// Function freeing it, such as `free`
+    pub dtor: String,
+    /// The call that returned it, as typed
+    pub call: String,
+}
+
+/// The pointers the REPL owns. One is freed as soon as neither a variable,
+/// a constant nor a result `$n` refers to it any more, see `:drop`.
+#[derive(Debug, Default)]
+pub struct Allocs {
+    live: Vec<Allocation>,
+}
+
+impl Allocs {
+    pub fn track(&mut self, addr: usize, dtor: &str, call: &str) {
+        // a freed address may come back from the allocator
+        self.live.retain(|a| a.addr != addr);
+        self.live.push(Allocation {
+            addr,
+            dtor: dtor.to_string(),
+            call: call.to_string(),
+        });
+    }
+
+    pub fn live(&self) -> &[Allocation] {
+        &self.live
+    }
+
+    /// Stops tracking the allocations nothing in `env` refers to, returning
+    /// them for their destructors to be called.
+    pub fn unheld(&mut self, env: &Env) -> Vec<Allocation> {
+        let (held, unheld) = self
+            .live
+            .drain(..)
+            .partition(|a| !holders(env, a.addr).is_empty());
+        self.live = held;
+        unheld
+    }
+
+    /// Stops tracking every allocation, for the session to free them.
+    pub fn take_all(&mut self) -> Vec<Allocation> {
+        std::mem::take(&mut self.live)
+    }
+}
+
+/// The results `$n`, variables and constants that hold `addr`. Results are
+/// kept for the whole session unless dropped, so one returned by a call
+/// stays held until `:drop` releases it.
+pub fn holders(env: &Env, addr: usize) -> Vec<String> {
+    let holds = |value: &Value| matches!(value, Value::Pointer(p) if *p == addr);
+    let mut names: Vec<String> = env
+        .vars
+        .iter()
+        .chain(&env.consts)
+        .filter(|(_, value)| holds(value))
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    let results = env
+        .history
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !entry.dropped && holds(&entry.value))
+        .map(|(i, _)| format!("${}", i + 1));
+    results.chain(names).collect()
+}
+
+/// `:allocs`: the live allocations, with what holds them.
+pub fn display_allocs(allocs: &Allocs, env: &Env) {
+    println!("INFO: Listing live allocations: ");
+    for alloc in allocs.live() {
+        println!(
+            "\t- {:#x} owned({}) <- {}, held by {}",
+            alloc.addr,
+            alloc.dtor,
+            alloc.call,
+            holders(env, alloc.addr).join(", ")
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::lex::Token;
+use crate::{error::Error, lex::Token};
 
 #[derive(Debug, Clone)]
 pub enum Expr {
@@ -6,7 +6,8 @@ pub enum Expr {
     Integer(i64),
     CString(String),
     CChar(char),
-    Variable(String),
+    /// A variable and the index of its token
+    Variable(String, usize),
     Unary(UnaryOp, Box<Expr>),
     Binary(Box<Expr>, BinaryOp, Box<Expr>),
 }
@@ -49,39 +50,29 @@ impl Parser {
         self.pos += 1;
     }
 
-    fn parse_primary(&mut self) -> Result<Expr, String> {
+    fn parse_primary(&mut self) -> Result<Expr, Error> {
+        let at = self.pos;
         let (token, text) = self
             .tokens
             .get(self.pos)
-            .ok_or("Unexpected end of input")?
+            .ok_or_else(|| Error::parse("Unexpected end of input").at(at))?
             .clone();
 
         self.pos += 1;
 
         match token {
-            Token::CInt => {
-                let value = if text.starts_with("0x") || text.starts_with("0X") {
-                    i64::from_str_radix(&text[2..], 16)
-                } else if text.starts_with("0b") || text.starts_with("0B") {
-                    i64::from_str_radix(&text[2..], 2)
-                } else if text.starts_with('0') && text.len() > 1 {
-                    i64::from_str_radix(&text[1..], 8)
-                } else {
-                    text.parse::<i64>()
-                }
-                .map_err(|_| format!("Invalid integer: {}", text))?;
-
-                Ok(Expr::Integer(value))
-            }
+            Token::CInt => Ok(Expr::Integer(
+                parse_int(&text).map_err(|e| Error::parse(e).at(at))?,
+            )),
             Token::CFloat => {
                 let value = text
                     .parse::<f64>()
-                    .map_err(|_| format!("Invalid float: {}", text))?;
+                    .map_err(|_| Error::parse(format!("Invalid float: {}", text)).at(at))?;
                 Ok(Expr::Number(value))
             }
-            Token::Id => Ok(Expr::Variable(text.to_string())),
+            Token::Id | Token::HistRef => Ok(Expr::Variable(text.to_string(), at)),
             Token::CString => Ok(Expr::CString(text.to_string())),
-            Token::CChar => Ok(Expr::CChar(text.chars().nth(1).unwrap())),
+            Token::CChar => Ok(Expr::CChar(text.chars().next().unwrap())),
             Token::LParen => {
                 let expr = self.parse_expr(0)?;
                 match self.peek() {
@@ -89,7 +80,7 @@ impl Parser {
                         self.advance();
                         Ok(expr)
                     }
-                    _ => Err("Expected ')'".to_string()),
+                    _ => Err(Error::parse("Expected ')'").at(self.pos)),
                 }
             }
             Token::Minus => {
@@ -100,11 +91,11 @@ impl Parser {
                 let expr = self.parse_primary()?;
                 Ok(Expr::Unary(UnaryOp::Not, Box::new(expr)))
             }
-            _ => Err(format!("Unexpected token: {:?}", token)),
+            _ => Err(Error::parse(format!("Unexpected token `{text}`")).at(at)),
         }
     }
 
-    fn parse_expr(&mut self, min_prec: u8) -> Result<Expr, String> {
+    fn parse_expr(&mut self, min_prec: u8) -> Result<Expr, Error> {
         let mut lhs = self.parse_primary()?;
 
         while let Some((token, _)) = self.peek() {
@@ -138,11 +129,35 @@ impl Parser {
         Ok(lhs)
     }
 
-    pub fn parse(&mut self) -> Result<Expr, String> {
-        self.parse_expr(0)
+    pub fn parse(&mut self) -> Result<Expr, Error> {
+        let expr = self.parse_expr(0)?;
+        match self.peek() {
+            Some((_, text)) => Err(Error::parse(format!("Unexpected token `{text}`")).at(self.pos)),
+            None => Ok(expr),
+        }
+    }
+}
+
+/// Parses a C integer literal: decimal, `0x` hex, `0b` binary or `0` octal.
+pub fn parse_int(text: &str) -> Result<i64, String> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if digits.starts_with("0x") || digits.starts_with("0X") {
+        i64::from_str_radix(&digits[2..], 16)
+    } else if digits.starts_with("0b") || digits.starts_with("0B") {
+        i64::from_str_radix(&digits[2..], 2)
+    } else if digits.starts_with('0') && digits.len() > 1 {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<i64>()
     }
+    .map(|value| sign * value)
+    .map_err(|_| format!("Invalid integer: {}", text))
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 enum Assoc {
     Left,
@@ -0,0 +1,492 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::cffi::FfiType;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CType {
+    Void,
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double,
+    /// `char*`, marshalled from and to REPL strings
+    String,
+    /// any other pointer
+    Pointer,
+}
+
+impl CType {
+    pub fn ffi_type(&self) -> FfiType {
+        match self {
+            CType::Void => FfiType::Void,
+            CType::Char => FfiType::SInt8,
+            CType::UChar => FfiType::UInt8,
+            CType::Short => FfiType::SInt16,
+            CType::UShort => FfiType::UInt16,
+            CType::Int => FfiType::SInt32,
+            CType::UInt => FfiType::UInt32,
+            CType::Long => FfiType::SInt64,
+            CType::ULong => FfiType::UInt64,
+            CType::Float => FfiType::Float,
+            CType::Double => FfiType::Double,
+            CType::String | CType::Pointer => FfiType::Pointer,
+        }
+    }
+
+    /// Parses a C type such as `const char *`, `unsigned long` or `size_t`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let stars = text.matches('*').count();
+        let words: Vec<&str> = text
+            .split(|c: char| c.is_whitespace() || c == '*')
+            .filter(|w| !w.is_empty() && !QUALIFIERS.contains(w))
+            .collect();
+        if stars > 0 {
+            return Ok(match (stars, words.as_slice()) {
+                (1, ["char"]) | (1, ["signed", "char"]) | (1, ["unsigned", "char"]) => {
+                    CType::String
+                }
+                _ => CType::Pointer,
+            });
+        }
+        let unsigned = words.contains(&"unsigned");
+        let words: Vec<&str> = words
+            .into_iter()
+            .filter(|w| *w != "unsigned" && *w != "signed")
+            .collect();
+        let ty = match (unsigned, words.as_slice()) {
+            (false, ["void"]) => CType::Void,
+            (false, ["char"]) | (false, ["int8_t"]) => CType::Char,
+            (true, ["char"]) | (false, ["uint8_t" | "_Bool" | "bool"]) => CType::UChar,
+            (false, ["short"] | ["short", "int"] | ["int16_t"]) => CType::Short,
+            (true, ["short"] | ["short", "int"]) | (false, ["uint16_t"]) => CType::UShort,
+            (false, ["int"] | ["int32_t"]) => CType::Int,
+            (true, [] | ["int"]) | (false, ["uint32_t"]) => CType::UInt,
+            (false, ["long"] | ["long", "int"] | ["long", "long"] | ["long", "long", "int"])
+            | (false, ["int64_t" | "ssize_t" | "ptrdiff_t" | "intptr_t" | "off_t"]) => CType::Long,
+            (true, ["long"] | ["long", "int"] | ["long", "long"] | ["long", "long", "int"])
+            | (false, ["uint64_t" | "size_t" | "uintptr_t"]) => CType::ULong,
+            (false, ["float"]) => CType::Float,
+            (false, ["double"]) => CType::Double,
+            _ => return Err(format!("Unknown C type `{}`", text.trim())),
+        };
+        Ok(ty)
+    }
+}
+
+const QUALIFIERS: &[&str] = &["const", "volatile", "restrict", "struct", "enum", "union"];
+
+impl Display for CType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CType::Void => "void",
+            CType::Char => "char",
+            CType::UChar => "unsigned char",
+            CType::Short => "short",
+            CType::UShort => "unsigned short",
+            CType::Int => "int",
+            CType::UInt => "unsigned int",
+            CType::Long => "long",
+            CType::ULong => "unsigned long",
+            CType::Float => "float",
+            CType::Double => "double",
+            CType::String => "char*",
+            CType::Pointer => "void*",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prototype {
+    pub ret: CType,
+    pub args: Vec<CType>,
+    pub variadic: bool,
+    /// Reports failures through `errno`, printed after every failing call.
+    pub sets_errno: bool,
+    /// Destructor of the returned pointer, the REPL then owns it
+    pub owned: Option<String>,
+    /// Out-params through which the function hands over a pointer, by index,
+    /// with the destructor of that pointer
+    pub owned_args: Vec<(usize, String)>,
+}
+
+/// `owned(free) char*` -> `(Some("free"), "char*")`.
+fn split_owned(text: &str) -> Result<(Option<String>, &str), String> {
+    let Some(rest) = text.trim_start().strip_prefix("owned(") else {
+        return Ok((None, text));
+    };
+    let (dtor, rest) = rest
+        .split_once(')')
+        .ok_or_else(|| format!("Unbalanced parentheses in `owned({rest}`"))?;
+    let dtor = dtor.trim();
+    if dtor.is_empty() || !dtor.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("`{dtor}` is not a destructor, e.g. `owned(free)`"));
+    }
+    Ok((Some(dtor.to_string()), rest))
+}
+
+fn owned_prefix(owned: &Option<String>) -> String {
+    owned
+        .as_ref()
+        .map(|dtor| format!("owned({dtor}) "))
+        .unwrap_or_default()
+}
+
+impl Prototype {
+    /// Parses a signature of the form `char*(const char*, int)`, optionally
+    /// preceded by annotations: `errno int(const char*, int)`. The return
+    /// type and pointer parameters may be annotated with the function that
+    /// frees them: `owned(free) char*(const char*)` or
+    /// `int(owned(free) char**, const char*, ...)`.
+    pub fn parse(sig: &str) -> Result<Self, String> {
+        let mut sig = sig.trim_start();
+        let mut sets_errno = false;
+        let mut owned = None;
+        loop {
+            let rest = match split_owned(sig)? {
+                (Some(dtor), rest) => {
+                    owned = Some(dtor);
+                    rest
+                }
+                (None, _) => match sig.split_once(char::is_whitespace) {
+                    Some(("errno", rest)) => {
+                        sets_errno = true;
+                        rest
+                    }
+                    _ => break,
+                },
+            };
+            sig = rest.trim_start();
+        }
+        let open = sig
+            .find('(')
+            .ok_or_else(|| format!("Expected `<ret>(<args>)`, got `{}`", sig.trim()))?;
+        let close = sig
+            .rfind(')')
+            .filter(|close| *close > open && sig[close + 1..].trim().is_empty())
+            .ok_or_else(|| format!("Unbalanced parentheses in `{}`", sig.trim()))?;
+        let ret = CType::parse(&sig[..open])?;
+        if owned.is_some() && !matches!(ret, CType::String | CType::Pointer) {
+            return Err(format!("`owned` needs a pointer return, not `{ret}`"));
+        }
+        let mut proto = Self {
+            ret,
+            args: Vec::new(),
+            variadic: false,
+            sets_errno,
+            owned,
+            owned_args: Vec::new(),
+        };
+        let params = sig[open + 1..close].trim();
+        if params.is_empty() || params == "void" {
+            return Ok(proto);
+        }
+        for param in params.split(',').map(str::trim) {
+            if param == "..." {
+                proto.variadic = true;
+                continue;
+            }
+            if proto.variadic {
+                return Err("`...` must be the last parameter".to_string());
+            }
+            let (dtor, param) = split_owned(param)?;
+            let ty = CType::parse(strip_param_name(param))?;
+            if let Some(dtor) = dtor {
+                if param.matches('*').count() < 2 {
+                    return Err(format!(
+                        "`owned` parameters hand a pointer over, so must be `T**`, not `{}`",
+                        param.trim()
+                    ));
+                }
+                proto.owned_args.push((proto.args.len(), dtor));
+            }
+            proto.args.push(ty);
+        }
+        Ok(proto)
+    }
+
+    /// The destructor of what the out-param at `index` hands over.
+    pub fn owned_arg(&self, index: usize) -> Option<&str> {
+        self.owned_args
+            .iter()
+            .find(|(i, _)| *i == index)
+            .map(|(_, dtor)| dtor.as_str())
+    }
+
+    fn params(&self) -> String {
+        let mut args: Vec<String> = self
+            .args
+            .iter()
+            .enumerate()
+            .map(|(i, a)| match self.owned_arg(i) {
+                // the pointed to type is lost, `void*` is all `CType` keeps
+                Some(dtor) => format!("owned({dtor}) void**"),
+                None => a.to_string(),
+            })
+            .collect();
+        if self.variadic {
+            args.push("...".to_string());
+        }
+        args.join(", ")
+    }
+
+    /// The signature as `:proto` takes it, `errno int(const char*, int)`.
+    pub fn signature(&self) -> String {
+        let annotations = if self.sets_errno { "errno " } else { "" };
+        let owned = owned_prefix(&self.owned);
+        format!("{annotations}{owned}{}({})", self.ret, self.params())
+    }
+
+    pub fn display(&self, name: &str) -> String {
+        let annotations = if self.sets_errno { "errno " } else { "" };
+        let owned = owned_prefix(&self.owned);
+        format!(
+            "{annotations}{owned}{} {}({})",
+            self.ret,
+            name,
+            self.params()
+        )
+    }
+}
+
+/// `const char *s` -> `const char *`, `size_t n` -> `size_t`, `size_t` -> `size_t`.
+fn strip_param_name(param: &str) -> &str {
+    let trimmed = param.trim_end();
+    let name_start = trimmed
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let (ty, name) = trimmed.split_at(name_start);
+    let has_type = ty
+        .split(|c: char| c.is_whitespace() || c == '*')
+        .any(|w| !w.is_empty() && !QUALIFIERS.contains(&w));
+    if name.is_empty() || !has_type || CType::parse(name).is_ok() || ty.trim() == "unsigned" {
+        trimmed
+    } else {
+        ty
+    }
+}
+
+/// Extracts the prototypes of the non-static functions defined in a C source.
+pub fn parse_c_definitions(src: &str) -> Vec<(String, Result<Prototype, String>)> {
+    let src = strip_comments_and_directives(src);
+    let mut out = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in src.char_indices() {
+        match c {
+            '{' => {
+                if depth == 0 {
+                    let head = src[start..i].trim();
+                    if let Some(def) = split_definition(head) {
+                        out.push(def);
+                    }
+                }
+                depth += 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    start = i + 1;
+                }
+            }
+            ';' if depth == 0 => start = i + 1,
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Extracts the prototypes of the functions a C header declares. Typedefs,
+/// function pointers and whatever is inside braces are skipped.
+pub fn parse_c_declarations(src: &str) -> Vec<(String, Result<Prototype, String>)> {
+    let src = strip_comments_and_directives(src);
+    let mut out = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in src.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    start = i + 1;
+                }
+            }
+            ';' if depth == 0 => {
+                let head = src[start..i].trim();
+                start = i + 1;
+                if head.starts_with("typedef") || head.contains("(*") {
+                    continue;
+                }
+                if let Some(decl) = split_definition(head) {
+                    out.push(decl);
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+fn split_definition(head: &str) -> Option<(String, Result<Prototype, String>)> {
+    let open = head.find('(')?;
+    let before = head[..open].trim_end();
+    let name_start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let name = &before[name_start..];
+    let ret = &before[..name_start];
+    let ret_words: Vec<&str> = ret.split_whitespace().collect();
+    if name.is_empty() || ret_words.contains(&"static") {
+        return None;
+    }
+    let ret: Vec<&str> = ret_words
+        .into_iter()
+        .filter(|w| !matches!(*w, "inline" | "extern"))
+        .collect();
+    let sig = format!("{}{}", ret.join(" "), &head[open..]);
+    Some((name.to_string(), Prototype::parse(&sig)))
+}
+
+fn strip_comments_and_directives(src: &str) -> String {
+    let mut out = String::new();
+    let mut rest = src;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("//") {
+            rest = after.find('\n').map(|i| &after[i..]).unwrap_or("");
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = after.find("*/").map(|i| &after[i + 2..]).unwrap_or("");
+        } else {
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out.lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Prototypes declared with `:proto` or taken from compiled snippets.
+#[derive(Debug, Default, Clone)]
+pub struct Protos {
+    protos: HashMap<String, Prototype>,
+}
+
+impl Protos {
+    pub fn set(&mut self, name: &str, proto: Prototype) {
+        self.protos.insert(name.to_string(), proto);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prototype> {
+        self.protos.get(name)
+    }
+
+    /// The prototypes sorted by function name.
+    pub fn sorted(&self) -> Vec<(&str, &Prototype)> {
+        let mut protos: Vec<(&str, &Prototype)> = self
+            .protos
+            .iter()
+            .map(|(name, proto)| (name.as_str(), proto))
+            .collect();
+        protos.sort_by_key(|(name, _)| *name);
+        protos
+    }
+
+    pub fn display(&self) {
+        println!("INFO: Listing prototypes: ");
+        let mut names: Vec<&String> = self.protos.keys().collect();
+        names.sort();
+        for name in names {
+            println!("\t- {}", self.protos[name].display(name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_signatures() {
+        let proto = Prototype::parse("char*(const char *s, size_t n)").unwrap();
+        assert_eq!(proto.ret, CType::String);
+        assert_eq!(proto.args, vec![CType::String, CType::ULong]);
+        assert!(!proto.variadic && !proto.sets_errno);
+        assert_eq!(Prototype::parse("int(void)").unwrap().args, vec![]);
+        assert_eq!(
+            Prototype::parse("unsigned(unsigned long, void *)")
+                .unwrap()
+                .args,
+            vec![CType::ULong, CType::Pointer]
+        );
+    }
+
+    #[test]
+    fn parses_annotations() {
+        let proto = Prototype::parse("errno owned(free) char*(const char*)").unwrap();
+        assert!(proto.sets_errno);
+        assert_eq!(proto.owned.as_deref(), Some("free"));
+        let proto = Prototype::parse("int(owned(free) char **out, const char *fmt, ...)").unwrap();
+        assert!(proto.variadic);
+        assert_eq!(proto.owned_arg(0), Some("free"));
+        assert_eq!(proto.owned_arg(1), None);
+        assert_eq!(proto.signature(), "int(owned(free) void**, char*, ...)");
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        for sig in [
+            "int",
+            "int(int",
+            "int(int) x",
+            "int(..., int)",
+            "owned(free) int(void)",
+            "int(owned(free) char*)",
+            "owned(1 2) char*(void)",
+            "widget(int)",
+        ] {
+            assert!(Prototype::parse(sig).is_err(), "`{sig}` was accepted");
+        }
+    }
+
+    #[test]
+    fn reads_header_declarations() {
+        let header = r#"
+            #ifndef FOO_H
+            #define FOO_H(x) (x)
+            /* a (commented) declaration: int gone(void); */
+            typedef int (*cmp_fn)(const void *, const void *);
+            typedef struct { int x; } point;
+            struct node { struct node *next; int (*visit)(int); };
+            extern const char *foo_name(int id); // which one
+            static inline int helper(int x) { return x; }
+            void foo_sort(void *base, size_t n, cmp_fn cmp);
+            unsigned long foo_count(void);
+            #endif
+        "#;
+        let decls = parse_c_declarations(header);
+        let names: Vec<&str> = decls.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["foo_name", "foo_sort", "foo_count"]);
+        let proto = decls[0].1.as_ref().unwrap();
+        assert_eq!(
+            (proto.ret, proto.args.as_slice()),
+            (CType::String, &[CType::Int][..])
+        );
+        // `cmp_fn` is a typedef the REPL can not know about
+        assert!(decls[1].1.is_err());
+        assert_eq!(decls[2].1.as_ref().unwrap().ret, CType::ULong);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{config::state_dir, error::Error, libpath::expand_home};
+
+/// File listing the project rc files the user agreed to run, one
+/// `<hash> <path>` line each.
+fn trust_file() -> PathBuf {
+    state_dir().join("trusted")
+}
+
+/// SHA-256 of `data` in hex, a file can not be made to match a trusted one
+/// without being the same.
+fn digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The rc scripts to run at startup: `~/.creplrc`, then `./.creplrc` as the
+/// project one, when they exist.
+pub fn rc_files() -> (Option<PathBuf>, Option<PathBuf>) {
+    let user = PathBuf::from(expand_home("~/.creplrc"));
+    let user = user.is_file().then_some(user);
+    let project = fs::canonicalize(".creplrc").ok().filter(|project| {
+        user.as_ref()
+            .and_then(|u| fs::canonicalize(u).ok())
+            .as_ref()
+            != Some(project)
+    });
+    (user, project)
+}
+
+/// Whether `path` was trusted with exactly the content `src`. Any edit to
+/// the file makes it untrusted again.
+pub fn is_trusted(path: &Path, src: &str) -> bool {
+    let entry = format!("{} {}", digest(src.as_bytes()), path.display());
+    fs::read_to_string(trust_file()).is_ok_and(|trusted| trusted.lines().any(|l| l == entry))
+}
+
+/// Records that `path`, as it is now, may run without asking.
+pub fn trust(path: &Path, src: &str) -> Result<(), Error> {
+    let file = trust_file();
+    let suffix = format!(" {}", path.display());
+    let mut trusted: Vec<String> = fs::read_to_string(&file)
+        .unwrap_or_default()
+        .lines()
+        .filter(|l| !l.ends_with(&suffix))
+        .map(str::to_string)
+        .collect();
+    trusted.push(format!("{}{suffix}", digest(src.as_bytes())));
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| Error::io(format!("Could not create {}: {e}", dir.display())))?;
+    }
+    fs::write(&file, trusted.join("\n") + "\n")
+        .map_err(|e| Error::io(format!("Could not write {}: {e}", file.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_are_sha256() {
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(digest(b":l libfoo.so\n"), digest(b":l libfoo.so \n"));
+    }
+}
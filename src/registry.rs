@@ -1,72 +1,504 @@
 use std::{
     collections::HashMap,
-    sync::{Mutex, OnceLock},
+    ffi::{c_int, c_void},
+    ops::Range,
+    path::Path,
 };
 
-use crate::dlfcn::{DlOpenFlags, DlSym, DynLib};
+use crate::{
+    dlfcn::{DlAddr, DlError, DlOpenFlags, DlSym, DynLib, LM_ID_NEWLM, Lmid, symbolize},
+    elf::dynamic_symbols,
+    error::Error,
+    eval::Env,
+    heap::interposed,
+    libpath::resolve_lib,
+    vars::vars_in_ranges,
+    watch::Inotify,
+};
 
-const RED: &str = "\x1b[31m";
-const RESET: &str = "\x1b[m";
+/// C source a library was compiled from with `:c` or `:cfile`.
+#[derive(Debug, Clone)]
+pub enum CSource {
+    Snippet(String),
+    File(String),
+}
 
-static DLIBS: OnceLock<Mutex<HashMap<String, DynLib>>> = OnceLock::new();
+pub struct LinkedLib {
+    pub path: String,
+    pub lib: DynLib,
+    /// Named link namespace the library was `dlmopen`ed in, `None` for the
+    /// default one.
+    pub ns: Option<String>,
+    pub compiled: Option<CSource>,
+}
+
+struct WatchedLib {
+    wd: c_int,
+    file_name: String,
+    path: String,
+}
+
+#[derive(Default)]
+struct Watches {
+    inotify: Option<Inotify>,
+    libs: HashMap<String, WatchedLib>,
+}
+
+/// The libraries a session linked, libc always being one of them.
+pub struct Libraries {
+    libs: HashMap<String, LinkedLib>,
+    /// Keys of `libs` in the order they were linked
+    order: Vec<String>,
+    /// Link-map ids of the named namespaces
+    namespaces: HashMap<String, Lmid>,
+    watches: Watches,
+}
 
-fn dlibs() -> &'static Mutex<HashMap<String, DynLib>> {
-    DLIBS.get_or_init(|| {
-        let mut hm = HashMap::new();
-        hm.insert(
+impl Default for Libraries {
+    fn default() -> Self {
+        let mut libs = HashMap::new();
+        libs.insert(
             "libc.so.6".to_string(),
-            DynLib::open("libc.so.6", &[DlOpenFlags::RTLD_LAZY]).unwrap(),
+            LinkedLib {
+                path: "libc.so.6".to_string(),
+                lib: DynLib::open("libc.so.6", &[DlOpenFlags::RTLD_LAZY]).unwrap(),
+                ns: None,
+                compiled: None,
+            },
         );
+        Self {
+            libs,
+            order: vec!["libc.so.6".to_string()],
+            namespaces: HashMap::new(),
+            watches: Watches::default(),
+        }
+    }
+}
 
-        Mutex::new(hm)
-    })
+/// Key of a library in the registry, `ns::libname` for namespaced ones.
+fn qualified(ns: Option<&str>, libname: &str) -> String {
+    match ns {
+        Some(ns) => format!("{ns}::{libname}"),
+        None => libname.to_string(),
+    }
 }
 
-pub fn add_lib(libname: &str) {
-    let lib = DynLib::open(libname, &[DlOpenFlags::RTLD_LAZY]);
-    match lib {
-        Ok(lib) => {
-            dlibs().lock().unwrap().insert(libname.to_string(), lib);
-        }
-        Err(e) => {
-            eprintln!("{RED}{}{RESET}", e);
-        }
+/// Splits `memcpy@@GLIBC_2.14` (or `memcpy@GLIBC_2.2.5`) into the symbol
+/// name and its version.
+pub fn split_version(sym: &str) -> (&str, Option<&str>) {
+    match sym.split_once('@') {
+        Some((name, version)) => (name, Some(version.trim_start_matches('@'))),
+        None => (sym, None),
     }
 }
 
-pub fn del_lib(libname: &str) {
-    if dlibs().lock().unwrap().remove(libname).is_none() {
-        eprintln!("{RED}The library {libname} was not linked to unlink{RESET}");
+/// Splits `old::decode` into the namespace and the symbol.
+pub fn split_namespace(sym: &str) -> (Option<&str>, &str) {
+    match sym.split_once("::") {
+        Some((ns, sym)) => (Some(ns), sym),
+        None => (None, sym),
     }
 }
 
-pub fn get_libs() {
-    println!("INFO: Listing linked libraries: ");
-    let libs = dlibs().lock().unwrap();
-    for (libname, _) in libs.iter() {
-        println!("\t- {libname}");
+/// `old::memcpy@@GLIBC_2.14` -> `memcpy`
+pub fn base_name(sym: &str) -> &str {
+    split_version(split_namespace(sym).1).0
+}
+
+/// Looks `sym`, possibly `name@VERSION`, up in `lib` alone.
+fn lookup_in(lib: &DynLib, sym: &str) -> Result<DlSym, DlError> {
+    match split_version(split_namespace(sym).1) {
+        (name, Some(version)) => DlSym::new_versioned(lib, name, version),
+        (name, None) => DlSym::new(lib, name),
     }
 }
 
-pub fn get_sym(sym: &str) -> Option<DlSym> {
-    let libs = dlibs().lock().unwrap();
-    let mut lookedup_libs = Vec::new();
-    for (libname, lib) in libs.iter() {
-        match DlSym::new(lib, sym) {
-            Ok(dlsym) => {
-                return Some(dlsym);
+impl Libraries {
+    fn open_lib(&mut self, path: &str, ns: Option<&str>) -> Result<DynLib, Error> {
+        let Some(ns) = ns else {
+            return Ok(DynLib::open(path, &[DlOpenFlags::RTLD_LAZY])?);
+        };
+        let lmid = self.namespaces.get(ns).copied().unwrap_or(LM_ID_NEWLM);
+        let lib = DynLib::open_in_namespace(path, lmid, &[DlOpenFlags::RTLD_LAZY]).or_else(
+            |e| match lmid {
+                LM_ID_NEWLM => Err(e),
+                // the namespace went away with the last library unlinked from it
+                _ => DynLib::open_in_namespace(path, LM_ID_NEWLM, &[DlOpenFlags::RTLD_LAZY]),
+            },
+        )?;
+        if let Some(lmid) = lib.namespace() {
+            self.namespaces.insert(ns.to_string(), lmid);
+        }
+        Ok(lib)
+    }
+
+    pub fn add_lib(&mut self, libname: &str) -> Result<(), Error> {
+        self.add_lib_in(libname, None)
+    }
+
+    /// Links `libname` into the named namespace `ns`, creating it with
+    /// `dlmopen(LM_ID_NEWLM, ...)` the first time it is used.
+    pub fn add_lib_in(&mut self, libname: &str, ns: Option<&str>) -> Result<(), Error> {
+        let path = resolve_lib(libname)?;
+        let lib = self.open_lib(&path, ns)?;
+        if path != libname {
+            println!("INFO: `{libname}` resolved to {path}");
+        }
+        let key = qualified(ns, libname);
+        self.order.retain(|k| *k != key);
+        self.order.push(key.clone());
+        self.libs.insert(
+            key,
+            LinkedLib {
+                path,
+                lib,
+                ns: ns.map(str::to_string),
+                compiled: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Links the shared object `:c` or `:cfile` compiled from `source`.
+    pub fn add_compiled(&mut self, so_file: &str, source: CSource) -> Result<(), Error> {
+        self.add_lib(so_file)?;
+        if let Some(linked) = self.libs.get_mut(so_file) {
+            linked.compiled = Some(source);
+        }
+        Ok(())
+    }
+
+    /// Unlinks `libname`, returning the address ranges it was mapped at.
+    pub fn del_lib(&mut self, libname: &str) -> Result<Vec<Range<usize>>, Error> {
+        let Some(old) = self.libs.remove(libname) else {
+            return Err(Error::link(format!(
+                "The library {libname} was not linked to unlink"
+            )));
+        };
+        let ranges = old.lib.mapped_ranges();
+        self.order.retain(|k| k != libname);
+        if self.watches.libs.contains_key(libname) {
+            self.unwatch_lib(libname)?;
+        }
+        Ok(ranges)
+    }
+
+    pub fn get_libs(&self) {
+        println!("INFO: Listing linked libraries: ");
+        for (libname, linked) in self.linked() {
+            let unqualified = split_namespace(libname).1;
+            if linked.path == unqualified {
+                println!("\t- {libname}");
+            } else {
+                println!("\t- {libname} ({})", linked.path);
             }
-            Err(_) => {
-                lookedup_libs.push(libname);
+        }
+    }
+
+    /// The linked libraries by key, in the order they were linked.
+    pub fn linked(&self) -> impl Iterator<Item = (&str, &LinkedLib)> {
+        self.order
+            .iter()
+            .filter_map(|key| Some((key.as_str(), self.libs.get(key)?)))
+    }
+
+    /// Keys of the watched libraries.
+    pub fn watched(&self) -> Vec<&str> {
+        let mut watched: Vec<&str> = self.watches.libs.keys().map(String::as_str).collect();
+        watched.sort();
+        watched
+    }
+
+    /// Files of the linked libraries, as the loader opened them.
+    pub fn lib_files(&self) -> Vec<String> {
+        self.libs
+            .values()
+            .map(|linked| {
+                linked
+                    .lib
+                    .file_name()
+                    .unwrap_or_else(|| linked.path.clone())
+            })
+            .collect()
+    }
+
+    /// Looks `sym` up in the libraries of its namespace, the default one when
+    /// it is not qualified with `ns::`.
+    pub fn get_sym(&self, sym: &str) -> Result<DlSym, Error> {
+        self.resolve(sym).map(|(_, dlsym)| dlsym)
+    }
+
+    /// Looks `sym` up like `get_sym`, along with the library it was found in.
+    pub fn resolve(&self, sym: &str) -> Result<(&str, DlSym), Error> {
+        let mut lookedup_libs = Vec::new();
+        let (ns, unqualified) = split_namespace(sym);
+        let (name, version) = split_version(unqualified);
+        if ns.is_none()
+            && version.is_none()
+            && let Some(dlsym) = interposed(name)
+        {
+            return Ok(("heapcheck.so", dlsym));
+        }
+        // the first library linked wins, the way the loader binds
+        for (libname, linked) in self.linked().filter(|(_, l)| l.ns.as_deref() == ns) {
+            match lookup_in(&linked.lib, unqualified) {
+                Ok(dlsym) => {
+                    return Ok((libname, dlsym));
+                }
+                Err(_) => {
+                    lookedup_libs.push(libname);
+                }
             }
         }
+        let mut msg = format!(
+            "Could not find the symbol `{sym}`, try linking it from a shared object, we looked up the following shared objects:"
+        );
+        lookedup_libs
+            .iter()
+            .for_each(|l| msg.push_str(&format!("\n\t- {l}")));
+        Err(Error::symbol(msg))
+    }
+
+    /// Lists the symbols `libname` defines, with their versions, keeping those
+    /// containing `filter`.
+    pub fn list_syms(&self, libname: &str, filter: Option<&str>) -> Result<(), Error> {
+        let path = match self.libs.get(libname) {
+            Some(linked) => linked
+                .lib
+                .file_name()
+                .unwrap_or_else(|| linked.path.clone()),
+            None => return Err(Error::link(format!("The library {libname} is not linked"))),
+        };
+        let syms = dynamic_symbols(&path).map_err(Error::link)?;
+        println!("INFO: Listing symbols of {path}: ");
+        for (name, versions) in syms
+            .iter()
+            .filter(|(name, _)| filter.is_none_or(|f| name.contains(f)))
+        {
+            let versions: Vec<String> = versions
+                .iter()
+                .map(|v| match v.default {
+                    true => format!("@@{}", v.version),
+                    false => format!("@{}", v.version),
+                })
+                .collect();
+            println!("\t- {name} {}", versions.join(" "));
+        }
+        Ok(())
     }
-    eprintln!(
-        "{RED}Could not find the symbol `{sym}`, try linking it from a shared object, we looked up the following shared objects:{RESET}"
-    );
-    lookedup_libs
-        .iter()
-        .for_each(|l| eprintln!("\t{RED}- {l}{RESET}"));
 
-    None
+    pub fn watch_lib(&mut self, libname: &str) -> Result<(), Error> {
+        let path = match self.libs.get(libname) {
+            Some(linked) => linked.path.clone(),
+            None => {
+                return Err(Error::link(format!(
+                    "The library {libname} is not linked, link it with `:l` first"
+                )));
+            }
+        };
+        if !path.contains('/') {
+            return Err(Error::link(format!(
+                "`{libname}` was found through the loader search path, link it by path to watch it"
+            )));
+        }
+        let file = Path::new(&path);
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let file_name = file
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let watches = &mut self.watches;
+        if watches.inotify.is_none() {
+            watches.inotify = Some(Inotify::new().map_err(Error::link)?);
+        }
+        let wd = watches
+            .inotify
+            .as_ref()
+            .unwrap()
+            .add_watch(dir)
+            .map_err(Error::link)?;
+        println!("INFO: watching {path} for changes");
+        watches.libs.insert(
+            libname.to_string(),
+            WatchedLib {
+                wd,
+                file_name,
+                path,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn unwatch_lib(&mut self, libname: &str) -> Result<(), Error> {
+        let watches = &mut self.watches;
+        let Some(watched) = watches.libs.remove(libname) else {
+            return Err(Error::link(format!(
+                "The library {libname} is not being watched"
+            )));
+        };
+        // several libraries in the same directory share one watch descriptor
+        if !watches.libs.values().any(|w| w.wd == watched.wd)
+            && let Some(inotify) = watches.inotify.as_ref()
+        {
+            inotify.rm_watch(watched.wd);
+        }
+        Ok(())
+    }
+
+    pub fn get_watched(&self) {
+        println!("INFO: Listing watched libraries: ");
+        for (libname, watched) in self.watches.libs.iter() {
+            println!("\t- {libname} ({})", watched.path);
+        }
+    }
+
+    /// Re-opens every watched library whose file changed since the last call.
+    /// The libraries that could not be reloaded and the dangling pointers the
+    /// reload left behind, variables of `env` included, come back as errors.
+    /// Globals bound in a reloaded library are looked up again in the new
+    /// image, and unbound when that fails.
+    pub fn reload_watched(&mut self, env: &mut Env) -> Vec<Error> {
+        let mut errors = Vec::new();
+        let Some(inotify) = self.watches.inotify.as_ref() else {
+            return errors;
+        };
+        let events = inotify.read_events();
+        let mut changed: Vec<(String, String)> = self
+            .watches
+            .libs
+            .iter()
+            .filter(|(_, w)| {
+                events
+                    .iter()
+                    .any(|(wd, name)| *wd == w.wd && *name == w.file_name)
+            })
+            .map(|(libname, w)| (libname.clone(), w.path.clone()))
+            .collect();
+        changed.sort();
+
+        for (libname, path) in changed {
+            let ns = split_namespace(&libname).0;
+            let (stale, globals, compiled, kept_base) = match self.libs.remove(&libname) {
+                Some(old) => {
+                    let ranges = old.lib.mapped_ranges();
+                    let compiled = old.compiled.clone();
+                    // dropping the old handle dlcloses it, otherwise dlopen
+                    // would hand back the image that is already mapped
+                    drop(old);
+                    // something else may keep it mapped all the same
+                    let kept_base = ranges
+                        .first()
+                        .map(|r| r.start)
+                        .filter(|base| DlAddr::lookup(*base).is_some());
+                    let globals = env.take_globals_in(&ranges);
+                    (vars_in_ranges(env, &ranges), globals, compiled, kept_base)
+                }
+                None => (Vec::new(), Vec::new(), None, None),
+            };
+            let mut stale_list = String::new();
+            stale.iter().for_each(|(name, addr)| {
+                stale_list.push_str(&format!(
+                    "\n\t- `{name}` = {addr:#x} pointed into the old image"
+                ))
+            });
+            match self.open_lib(&path, ns) {
+                Ok(lib) => {
+                    let same_image = kept_base.is_some()
+                        && lib.mapped_ranges().first().map(|r| r.start) == kept_base;
+                    match same_image {
+                        true => errors.push(Error::link(format!(
+                            "`{libname}` changed on disk but its old image is still mapped, opened with RTLD_NODELETE or by another library, the changes are not loaded"
+                        ))),
+                        false => {
+                            println!("INFO: `{libname}` changed on disk, reloaded {path}");
+                            errors.push(Error::memory(format!(
+                                "function pointers, callbacks and buffers obtained from the previous image of `{libname}` are now dangling{stale_list}"
+                            )));
+                        }
+                    }
+                    // only the reloaded library, another one may define the
+                    // same name
+                    for (name, mut global) in globals {
+                        match lookup_in(&lib, &global.sym) {
+                            Ok(sym) => {
+                                global.addr = <*mut c_void>::from(sym) as usize;
+                                if !same_image {
+                                    println!(
+                                        "INFO: `{name}` bound again at {}",
+                                        symbolize(global.addr)
+                                    );
+                                }
+                                // the library is reloaded, so the name is free
+                                let _ = env.set_global(name, global);
+                            }
+                            Err(e) => errors.push(Error::symbol(format!(
+                                "`{name}` is unbound, `{libname}` no longer defines it: {e}"
+                            ))),
+                        }
+                    }
+                    self.libs.insert(
+                        libname.clone(),
+                        LinkedLib {
+                            path: path.clone(),
+                            lib,
+                            ns: ns.map(str::to_string),
+                            compiled,
+                        },
+                    );
+                }
+                Err(e) => {
+                    // the old image is gone already, there is nothing left to watch
+                    let _ = self.unwatch_lib(&libname);
+                    errors.push(Error::link(format!(
+                        "Could not reload `{libname}`, it is unlinked and no longer watched: {}{stale_list}",
+                        e.msg
+                    )));
+                    for (name, _) in globals {
+                        errors.push(Error::symbol(format!(
+                            "`{name}` is unbound, it lived in `{libname}`"
+                        )));
+                    }
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process::Command};
+
+    #[test]
+    fn resolves_in_link_order() {
+        let dir = env::temp_dir().join(format!("creplrs-resolve-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut libs = Libraries::default();
+        let mut paths = Vec::new();
+        for i in 0..8 {
+            let c_file = dir.join(format!("which{i}.c"));
+            let so_file = dir.join(format!("libwhich{i}.so"));
+            fs::write(&c_file, format!("int which(void) {{ return {i}; }}")).unwrap();
+            let status = Command::new("cc")
+                .args(["-shared", "-fPIC", "-o"])
+                .arg(&so_file)
+                .arg(&c_file)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            paths.push(so_file.display().to_string());
+        }
+        // linked last to first, a hash map would not keep that order
+        for path in paths.iter().rev() {
+            libs.add_lib(path).unwrap();
+        }
+        let (libname, _) = libs.resolve("which").unwrap();
+        assert_eq!(libname, paths[7]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
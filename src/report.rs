@@ -0,0 +1,138 @@
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[m";
+
+/// One `:assert`/`:expect` line of a test script, or the command that
+/// aborted it.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub line: usize,
+    pub command: String,
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptReport {
+    pub path: String,
+    pub checks: Vec<Check>,
+}
+
+impl ScriptReport {
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|c| c.failure.is_some()).count()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Human,
+    Tap,
+    Junit,
+}
+
+pub fn print_report(reports: &[ScriptReport], format: ReportFormat) {
+    match format {
+        ReportFormat::Human => print_human(reports),
+        ReportFormat::Tap => print_tap(reports),
+        ReportFormat::Junit => print_junit(reports),
+    }
+}
+
+fn print_human(reports: &[ScriptReport]) {
+    for report in reports {
+        match report.failures() {
+            0 => println!(
+                "{GREEN}ok{RESET}   {} ({} checks)",
+                report.path,
+                report.checks.len()
+            ),
+            _ => println!("{RED}FAIL{RESET} {}", report.path),
+        }
+        for check in &report.checks {
+            if let Some(failure) = &check.failure {
+                println!(
+                    "\t{RED}{}:{}: {failure}{RESET}\n\t    {}",
+                    report.path, check.line, check.command
+                );
+            }
+        }
+    }
+    let total: usize = reports.iter().map(|r| r.checks.len()).sum();
+    let failed: usize = reports.iter().map(ScriptReport::failures).sum();
+    println!(
+        "\n{} passed, {failed} failed in {} scripts",
+        total - failed,
+        reports.len()
+    );
+}
+
+/// TAP version 13, one test point per check.
+fn print_tap(reports: &[ScriptReport]) {
+    let total: usize = reports.iter().map(|r| r.checks.len()).sum();
+    println!("TAP version 13");
+    println!("1..{total}");
+    let checks = reports
+        .iter()
+        .flat_map(|r| r.checks.iter().map(move |c| (r, c)));
+    for (n, (report, check)) in checks.enumerate() {
+        let status = match check.failure {
+            Some(_) => "not ok",
+            None => "ok",
+        };
+        println!(
+            "{status} {} - {}:{} {}",
+            n + 1,
+            report.path,
+            check.line,
+            check.command
+        );
+        if let Some(failure) = &check.failure {
+            println!("  ---\n  message: {failure:?}\n  ...");
+        }
+    }
+}
+
+/// JUnit XML, one `<testsuite>` per script.
+fn print_junit(reports: &[ScriptReport]) {
+    let total: usize = reports.iter().map(|r| r.checks.len()).sum();
+    let failed: usize = reports.iter().map(ScriptReport::failures).sum();
+    println!(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    println!(r#"<testsuites tests="{total}" failures="{failed}">"#);
+    for report in reports {
+        let path = xml_escape(&report.path);
+        println!(
+            r#"  <testsuite name="{path}" tests="{}" failures="{}">"#,
+            report.checks.len(),
+            report.failures()
+        );
+        for check in &report.checks {
+            let name = xml_escape(&format!("line {}: {}", check.line, check.command));
+            match &check.failure {
+                None => println!(r#"    <testcase classname="{path}" name="{name}"/>"#),
+                Some(failure) => {
+                    println!(r#"    <testcase classname="{path}" name="{name}">"#);
+                    println!(r#"      <failure message="{}"/>"#, xml_escape(failure));
+                    println!("    </testcase>");
+                }
+            }
+        }
+        println!("  </testsuite>");
+    }
+    println!("</testsuites>");
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' => out.push_str("&#10;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,293 @@
+use std::{collections::HashSet, ffi::c_void, fs, path::Path};
+
+use crate::{
+    call::{CallOutput, call, invoke},
+    cffi::{ABI, ArgArena, CifCache, FFI_DEFAULT_ABI, FfiType},
+    cli::OpMode,
+    command::find_command,
+    compile,
+    error::Error,
+    eval::{Env, Value},
+    heap::HeapLog,
+    lex::{Token, lex_spanned},
+    owned::{Allocation, Allocs},
+    proto::Protos,
+    registry::Libraries,
+    snapshot::session_script,
+    trace::Tracer,
+    vars::initial_env,
+};
+
+/// What evaluating a line produced.
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// A command ran, printing whatever it had to show
+    Done,
+    Call(CallOutput),
+}
+
+/// Everything a REPL evaluates against: its variables and results, the
+/// libraries it linked and the prototypes it knows. Sessions are
+/// independent of each other, only the `:` command registry and the library
+/// search path are shared by the whole process.
+///
+/// ```
+/// use CREPLrs::{eval::Value, session::Session};
+///
+/// let mut session = Session::new();
+/// session.eval_line(":proto abs int(int)").unwrap();
+/// let res = session.call("abs", &[Value::Integer(-3)]).unwrap();
+/// assert!(matches!(res, Some(Value::Integer(3))));
+/// ```
+pub struct Session {
+    pub env: Env,
+    pub libs: Libraries,
+    pub protos: Protos,
+    /// Return type of calls to functions without a prototype
+    pub mode: OpMode,
+    /// Calling convention of calls, see `:abi`
+    pub abi: ABI,
+    /// `:trace` state
+    pub trace: Tracer,
+    /// Call interfaces prepared so far, by signature
+    pub cifs: CifCache,
+    /// Argument storage reused by every call
+    pub arena: ArgArena,
+    /// Pointers returned by functions declared `owned(...)`
+    pub allocs: Allocs,
+    /// Calls allocations were recorded in, with `--heapcheck`
+    pub heap: HeapLog,
+    /// Constants the config and rc files defined, which `:save` leaves out
+    /// since they are defined again on the next start
+    pub startup_consts: HashSet<String>,
+    /// What went wrong without failing the lines evaluated since the front
+    /// end last took it, see `take_warnings`
+    pub warnings: Vec<Error>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            env: initial_env(),
+            libs: Libraries::default(),
+            protos: Protos::default(),
+            mode: OpMode::Void,
+            abi: FFI_DEFAULT_ABI,
+            trace: Tracer::default(),
+            cifs: CifCache::default(),
+            arena: ArgArena::default(),
+            allocs: Allocs::default(),
+            heap: HeapLog::default(),
+            startup_consts: HashSet::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Records the constants defined so far as coming from startup, see
+    /// `startup_consts`.
+    pub fn mark_startup(&mut self) {
+        self.startup_consts = self.env.consts.keys().cloned().collect();
+    }
+
+    /// Evaluates one line as typed at the prompt, a `:` command or a call.
+    /// Errors carry the span of `line` they blame when there is one.
+    pub fn eval_line(&mut self, line: &str) -> Result<Output, Error> {
+        let spanned = lex_spanned(line)?;
+        let (tokens, mut spans): (Vec<_>, Vec<_>) = spanned.into_iter().unzip();
+        // errors about a missing token point past the end of the line
+        spans.push(line.len()..line.len());
+        self.run(tokens)
+            .map_err(|e| match e.token.and_then(|i| spans.get(i)) {
+                Some(span) if e.span.is_none() => {
+                    let span = span.clone();
+                    e.with_span(span)
+                }
+                _ => e,
+            })
+    }
+
+    /// Evaluates an already lexed line. Errors blame tokens by their index in
+    /// `tokens`.
+    pub fn run(&mut self, tokens: Vec<(Token, String)>) -> Result<Output, Error> {
+        let Some(first) = tokens.first() else {
+            return Ok(Output::Done);
+        };
+        if first.0 != Token::Command {
+            let out = call(self, &tokens)?;
+            self.free_unheld()?;
+            return Ok(Output::Call(out));
+        }
+        let cmd = find_command(&first.1).ok_or_else(|| {
+            Error::parse(format!("unknown command `{}`, see `:help`", first.1)).at(0)
+        })?;
+        cmd.run(&tokens[1..], self).map_err(|e| e.shifted(1))?;
+        self.free_unheld()?;
+        Ok(Output::Done)
+    }
+
+    /// Frees the owned pointers no variable, constant or result refers to any
+    /// more, after a value was overwritten.
+    pub fn free_unheld(&mut self) -> Result<(), Error> {
+        let unheld = self.allocs.unheld(&self.env);
+        self.free(unheld)
+    }
+
+    /// Calls the destructor of every allocation. One that can not be found
+    /// leaves its pointer leaked with a warning.
+    fn free(&mut self, allocs: Vec<Allocation>) -> Result<(), Error> {
+        for alloc in allocs {
+            let dtor = match self.libs.resolve(&alloc.dtor) {
+                Ok((_, dtor)) => dtor,
+                Err(e) => {
+                    self.warnings.push(Error::memory(format!(
+                        "leaking {:#x} from `{}`: {}",
+                        alloc.addr, alloc.call, e.msg
+                    )));
+                    continue;
+                }
+            };
+            let cif = self.cifs.get::<()>(FFI_DEFAULT_ABI, &[FfiType::Pointer])?;
+            let mut addr = alloc.addr;
+            cif.call(
+                dtor.addr() as *mut c_void,
+                &[&mut addr as *mut usize as *mut c_void],
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes a script rebuilding this session to `path`, see `:save`.
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let script = session_script(self, Path::new(path))?;
+        fs::write(path, script).map_err(|e| Error::io(format!("Could not write {path}: {e}")))
+    }
+
+    /// Runs the script at `path`, one saved with `:save` or any other, in
+    /// this session, stopping at the first line that fails.
+    pub fn load(&mut self, path: &str) -> Result<(), Error> {
+        let src = fs::read_to_string(path)
+            .map_err(|e| Error::io(format!("Could not read {path}: {e}")))?;
+        for (i, line) in src.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            self.eval_line(line)
+                .map_err(|e| Error::new(e.kind, format!("{path}:{}: {}", i + 1, e.msg)))?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the watched libraries that changed on disk, returning what went
+    /// wrong for the front end to report.
+    pub fn reload_watched(&mut self) -> Vec<Error> {
+        self.libs.reload_watched(&mut self.env)
+    }
+
+    /// The warnings of the lines evaluated since the last time, for the front
+    /// end to report.
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Frees the owned pointers that are left and removes the objects
+    /// compiled so far, returning what went wrong along with the warnings not
+    /// taken yet. Dropping the session does the same without reporting it.
+    pub fn close(&mut self) -> Vec<Error> {
+        let allocs = self.allocs.take_all();
+        if let Err(e) = self.free(allocs) {
+            self.warnings.push(e);
+        }
+        if let Err(e) = compile::remove_work_dir() {
+            self.warnings.push(e);
+        }
+        self.take_warnings()
+    }
+
+    /// Gives back the arena a `Prepared` call took, for the next call.
+    pub fn reuse_arena(&mut self, arena: ArgArena) {
+        self.arena = arena;
+    }
+
+    /// Calls `sym` with `args`, converted by its prototype when it has one.
+    /// The result is recorded in the history like a typed call, `None` for a
+    /// `void` function.
+    pub fn call(&mut self, sym: &str, args: &[Value]) -> Result<Option<Value>, Error> {
+        let call_text = std::iter::once(sym.to_string())
+            .chain(args.iter().map(|arg| format!("{arg:?}")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let value = invoke(self, sym, args, call_text)?.value;
+        self.free_unheld()?;
+        Ok(value)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::owned::holders;
+
+    #[test]
+    fn results_keep_owned_pointers_alive() {
+        let mut session = Session::new();
+        session
+            .eval_line(":proto strdup owned(free) char*(const char*)")
+            .unwrap();
+        session
+            .eval_line(":proto strlen unsigned long(const char*)")
+            .unwrap();
+        session.eval_line("strdup \"a\"").unwrap();
+        session.eval_line("strdup \"bc\"").unwrap();
+        let first = session.allocs.live()[0].addr;
+        assert_eq!(session.allocs.live().len(), 2);
+        assert_eq!(holders(&session.env, first), ["$1"]);
+
+        let Output::Call(out) = session.eval_line("strlen $1").unwrap() else {
+            panic!("`strlen $1` is not a call");
+        };
+        assert!(matches!(out.value, Some(Value::Integer(1))));
+        session.eval_line(":var p $1").unwrap();
+        assert_eq!(holders(&session.env, first), ["$1", "p"]);
+    }
+
+    #[test]
+    fn dropped_results_release_owned_pointers() {
+        let mut session = Session::new();
+        session
+            .eval_line(":proto strdup owned(free) char*(const char*)")
+            .unwrap();
+        session.eval_line("strdup \"a\"").unwrap();
+        let addr = session.allocs.live()[0].addr;
+        session.eval_line(":var p $1").unwrap();
+        session.eval_line(":drop $1").unwrap();
+        assert_eq!(holders(&session.env, addr), ["p"]);
+        assert!(session.eval_line("strlen $1").is_err());
+
+        session.eval_line(":var p 0").unwrap();
+        assert!(session.allocs.live().is_empty());
+        assert!(session.eval_line(":drop $1").is_err());
+    }
+
+    #[test]
+    fn owned_functions_are_not_benchmarked() {
+        let mut session = Session::new();
+        session
+            .eval_line(":proto strdup owned(free) char*(const char*)")
+            .unwrap();
+        assert!(session.eval_line(":bench -n 10 strdup \"a\"").is_err());
+        assert!(session.allocs.live().is_empty());
+    }
+}
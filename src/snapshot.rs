@@ -0,0 +1,164 @@
+use std::{fs, path::Path};
+
+use crate::{
+    cffi::{FFI_DEFAULT_ABI, abi_names},
+    cli::OpMode,
+    error::Error,
+    eval::Value,
+    libpath::added_search_paths,
+    registry::{CSource, split_namespace},
+    session::Session,
+    vars::initial_env,
+};
+
+/// `value` as a literal the lexer reads back, `None` when there is none.
+fn literal(value: &Value) -> Option<String> {
+    match value {
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Number(n) if n.is_finite() => Some(format!("{n:?}")),
+        Value::Bool(true) => Some("TRUE".to_string()),
+        Value::Bool(false) => Some("FALSE".to_string()),
+        Value::CString(s) if !s.contains(['"', '\\', '\n']) => Some(format!("\"{s}\"")),
+        Value::CChar(c) if *c != '\n' => Some(format!("'{c}'")),
+        _ => None,
+    }
+}
+
+/// A path argument, quoted when it has to be.
+fn path_arg(path: &str) -> String {
+    match path.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        true => format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => path.to_string(),
+    }
+}
+
+fn mode_command(mode: &OpMode) -> &'static str {
+    match mode {
+        OpMode::Float => ":f",
+        OpMode::Int => ":d",
+        OpMode::Ptr => ":s",
+        OpMode::Char => ":c",
+        OpMode::Void => ":v",
+    }
+}
+
+/// Renders `session` as a script that rebuilds it when run, the format of
+/// the files `:save` writes to `path`. Snippets that can not be put on a
+/// single line, because of preprocessor directives or `//` comments, are
+/// written next to it and compiled with `:cfile`.
+pub fn session_script(session: &Session, path: &Path) -> Result<String, Error> {
+    let mut out = vec!["# CREPLrs session".to_string()];
+    let mut snippets = 0;
+
+    for dir in added_search_paths() {
+        out.push(format!(":libpath add {}", path_arg(&dir)));
+    }
+    for (key, linked) in session.libs.linked() {
+        match &linked.compiled {
+            Some(CSource::File(c_file)) => out.push(format!(":cfile {}", path_arg(c_file))),
+            Some(CSource::Snippet(src))
+                if !src.contains("//") && !src.lines().any(|l| l.trim_start().starts_with('#')) =>
+            {
+                out.push(format!(":c {{ {} }}", src.trim().replace('\n', " ")));
+            }
+            Some(CSource::Snippet(src)) => {
+                snippets += 1;
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let c_file = path.with_file_name(format!("{stem}.snippet{snippets}.c"));
+                fs::write(&c_file, src)
+                    .map_err(|e| Error::io(format!("Could not write {}: {e}", c_file.display())))?;
+                let c_file = fs::canonicalize(&c_file).unwrap_or(c_file);
+                out.push(format!(
+                    ":cfile {}",
+                    path_arg(&c_file.display().to_string())
+                ));
+            }
+            None if key == "libc.so.6" => {}
+            None => match &linked.ns {
+                Some(ns) => out.push(format!(":l --ns {ns} {}", path_arg(split_namespace(key).1))),
+                None => out.push(format!(":l {}", path_arg(key))),
+            },
+        }
+    }
+    for key in session.libs.watched() {
+        out.push(format!(":watch {}", path_arg(key)));
+    }
+    for (name, proto) in session.protos.sorted() {
+        out.push(format!(":proto {name} {}", proto.signature()));
+    }
+
+    let env = &session.env;
+    let mut globals: Vec<_> = env.globals.iter().collect();
+    globals.sort_by_key(|(name, _)| *name);
+    for (name, global) in globals {
+        out.push(format!(":global {} {name}", global.ty));
+    }
+    let initial = initial_env();
+    let mut consts: Vec<_> = env
+        .consts
+        .iter()
+        .filter(|(name, _)| {
+            !initial.consts.contains_key(*name) && !session.startup_consts.contains(*name)
+        })
+        .collect();
+    consts.sort_by_key(|(name, _)| *name);
+    let mut vars: Vec<_> = env.vars.iter().collect();
+    vars.sort_by_key(|(name, _)| *name);
+    for (command, (name, value)) in consts
+        .into_iter()
+        .map(|c| (":const", c))
+        .chain(vars.into_iter().map(|v| (":var", v)))
+    {
+        match literal(value) {
+            Some(literal) => out.push(format!("{command} {name} {literal}")),
+            None => out.push(format!("# `{name}` = {value:?} has no literal form")),
+        }
+    }
+    if session.abi != FFI_DEFAULT_ABI
+        && let Some(name) = abi_names(session.abi).first()
+    {
+        out.push(format!(":abi {name}"));
+    }
+    out.push(mode_command(&session.mode).to_string());
+    out.push(String::new());
+    Ok(out.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn saved_sessions_load_back() {
+        let path = env::temp_dir().join(format!("creplrs-session-test-{}", std::process::id()));
+        // `RC` stands for a constant an rc file defines on every start
+        let start = || {
+            let mut session = Session::new();
+            session.eval_line(":const RC 1").unwrap();
+            session.mark_startup();
+            session
+        };
+        let mut session = start();
+        for line in [
+            ":const limit 10",
+            ":var greeting \"hi there\"",
+            ":var ratio 0.5",
+            ":var flag TRUE",
+            ":proto strdup owned(free) char*(const char*)",
+            ":proto printf int(const char*, ...)",
+            ":d",
+        ] {
+            session.eval_line(line).unwrap();
+        }
+        let script = session_script(&session, &path).unwrap();
+        assert!(!script.contains("RC"));
+
+        fs::write(&path, &script).unwrap();
+        let mut loaded = start();
+        let res = loaded.load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        res.unwrap();
+        assert_eq!(session_script(&loaded, &path).unwrap(), script);
+    }
+}
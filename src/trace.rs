@@ -0,0 +1,196 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{cffi::FfiType, dlfcn::symbolize, error::Error, eval::Value, proto::CType};
+
+/// An argument as it was handed to `ffi_call`.
+#[derive(Debug, Clone)]
+pub struct TraceArg {
+    pub ty: FfiType,
+    pub value: Value,
+    /// Where the argument was stored for the call
+    pub addr: usize,
+    /// The `char*` a string was passed as
+    pub ptr: Option<usize>,
+}
+
+/// Everything `:trace` reports about one call.
+#[derive(Debug, Clone)]
+pub struct CallTrace {
+    pub lib: String,
+    pub sym: String,
+    pub addr: usize,
+    pub args: Vec<TraceArg>,
+    pub ret: CType,
+    pub value: Option<Value>,
+    /// The return register before it was converted to `ret`
+    pub raw: u64,
+    pub errno: i32,
+    pub elapsed: Duration,
+}
+
+/// State of `:trace`: whether calls are traced, and the file they are also
+/// logged to as JSON lines.
+#[derive(Debug, Default)]
+pub struct Tracer {
+    pub enabled: bool,
+    log: Option<(String, File)>,
+    /// Calls traced since the front end last took them, see `take_recorded`
+    recorded: Vec<CallTrace>,
+}
+
+impl Tracer {
+    /// Starts tracing, appending to `log` as well when given.
+    pub fn start(&mut self, log: Option<&str>) -> Result<(), Error> {
+        self.log = match log {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| Error::io(format!("Could not open {path}: {e}")))?;
+                Some((path.to_string(), file))
+            }
+            None => None,
+        };
+        self.enabled = true;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.enabled = false;
+        self.log = None;
+    }
+
+    pub fn log_path(&self) -> Option<&str> {
+        self.log.as_ref().map(|(path, _)| path.as_str())
+    }
+
+    /// Keeps `trace` for the front end to show and appends it to the log
+    /// file.
+    pub fn record(&mut self, trace: CallTrace) -> Result<(), Error> {
+        let logged = match &mut self.log {
+            Some((path, file)) => writeln!(file, "{}", trace.json())
+                .map_err(|e| Error::io(format!("Could not write {path}: {e}"))),
+            None => Ok(()),
+        };
+        self.recorded.push(trace);
+        logged
+    }
+
+    /// The calls traced since the last time, oldest first.
+    pub fn take_recorded(&mut self) -> Vec<CallTrace> {
+        std::mem::take(&mut self.recorded)
+    }
+}
+
+fn show_arg(value: &Value) -> String {
+    match value {
+        Value::CString(s) => format!("{s:?}"),
+        Value::CChar(c) => format!("{c:?}"),
+        value => value.to_string(),
+    }
+}
+
+impl CallTrace {
+    /// The human readable form, one line for the call, one per argument and
+    /// one for the return.
+    pub fn render(&self) -> String {
+        let mut out = format!(
+            "TRACE: {}!{} at {} ({:?})\n",
+            self.lib,
+            self.sym,
+            symbolize(self.addr),
+            self.elapsed
+        );
+        for (i, arg) in self.args.iter().enumerate() {
+            let ptr = arg.ptr.map(|p| format!(" -> {p:#x}")).unwrap_or_default();
+            out.push_str(&format!(
+                "\targ {}: {:?} {}{ptr} at {:#x}\n",
+                i + 1,
+                arg.ty,
+                show_arg(&arg.value),
+                arg.addr
+            ));
+        }
+        let value = self.value.as_ref().map(show_arg).unwrap_or_default();
+        out.push_str(&format!(
+            "\treturn: {} {value} (raw {:#018x}), errno {}\n",
+            self.ret, self.raw, self.errno
+        ));
+        out
+    }
+
+    /// One JSON object, for the log file.
+    pub fn json(&self) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| {
+                let ptr = arg
+                    .ptr
+                    .map(|p| format!(",\"ptr\":\"{p:#x}\""))
+                    .unwrap_or_default();
+                format!(
+                    "{{\"type\":\"{:?}\",\"value\":{},\"addr\":\"{:#x}\"{ptr}}}",
+                    arg.ty,
+                    json_value(&arg.value),
+                    arg.addr
+                )
+            })
+            .collect();
+        format!(
+            "{{\"timestamp_us\":{timestamp},\"lib\":{},\"sym\":{},\"addr\":\"{:#x}\",\"args\":[{}],\
+             \"ret\":{{\"type\":{},\"value\":{},\"raw\":\"{:#x}\"}},\"errno\":{},\"elapsed_ns\":{}}}",
+            json_string(&self.lib),
+            json_string(&self.sym),
+            self.addr,
+            args.join(","),
+            json_string(&self.ret.to_string()),
+            self.value
+                .as_ref()
+                .map(json_value)
+                .unwrap_or("null".to_string()),
+            self.raw,
+            self.errno,
+            self.elapsed.as_nanos()
+        )
+    }
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Number(n) if n.is_finite() => format!("{n:?}"),
+        Value::Number(n) => json_string(&n.to_string()),
+        Value::Bool(b) => b.to_string(),
+        Value::CString(s) => json_string(s),
+        Value::CChar(c) => json_string(&c.to_string()),
+        Value::Pointer(p) => json_string(&format!("{p:#x}")),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,12 +1,15 @@
-use crate::{lex::Token, parser::Parser};
+use crate::{
+    error::Error,
+    lex::Token,
+    parser::{Expr, Parser},
+};
 
-use crate::eval::{Env, Value, eval};
+use crate::eval::{Env, Value, eval, show_result};
 
-use chumsky::container::Seq;
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use std::ops::Range;
 
-static GLOBAL_ENV: Lazy<Mutex<Env>> = Lazy::new(|| {
+/// A new environment with the predefined constants.
+pub fn initial_env() -> Env {
     let mut env = Env::new();
     env.set_const("PI".to_string(), Value::Number(std::f64::consts::PI))
         .unwrap();
@@ -16,77 +19,115 @@ static GLOBAL_ENV: Lazy<Mutex<Env>> = Lazy::new(|| {
         .unwrap();
     env.set_const("FALSE".to_string(), Value::Bool(false))
         .unwrap();
-    Mutex::new(env)
-});
+    env.set_errno(0);
+    env
+}
 
-pub fn const_eval(tokens: Vec<(Token, String)>) -> Result<String, String> {
+pub fn const_eval(env: &mut Env, tokens: Vec<(Token, String)>) -> Result<String, Error> {
     if tokens.is_empty() {
-        return Err("Usage: :const <name> <expression>".to_string());
+        return Err(Error::parse("Usage: :const <name> <expression>"));
     }
 
     // First token is the constant name
     let name = match &tokens[0] {
         (Token::Id, name) => name.clone(),
-        _ => return Err("Constant name must be an identifier".to_string()),
+        _ => return Err(Error::parse("Constant name must be an identifier").at(0)),
     };
 
     let mut parser = Parser::new(tokens[1..].to_vec());
-    let expr = parser.parse()?;
-
-    let mut env = GLOBAL_ENV.lock().unwrap();
+    let expr = parser.parse().map_err(|e| e.shifted(1))?;
 
-    let value = eval(&expr, &env)?;
+    let value = eval(&expr, env).map_err(|e| e.shifted(1))?;
 
-    env.set_const(name.clone(), value)?;
+    env.set_const(name.clone(), value).map_err(|e| e.at(0))?;
 
     Ok(format!("Constant '{}' defined", name))
 }
 
-pub fn var_eval(tokens: Vec<(Token, String)>) -> Result<String, String> {
+pub fn var_eval(env: &mut Env, tokens: Vec<(Token, String)>) -> Result<String, Error> {
     if tokens.is_empty() {
-        return Err("Usage: :var <name> <expression>".to_string());
+        return Err(Error::parse("Usage: :var <name> <expression>"));
     }
 
     let name = match &tokens[0] {
         (Token::Id, name) => name.clone(),
-        _ => return Err("Variable name must be an identifier".to_string()),
+        _ => return Err(Error::parse("Variable name must be an identifier").at(0)),
     };
 
     let mut parser = Parser::new(tokens[1..].to_vec());
-    let expr = parser.parse()?;
-
-    let mut env = GLOBAL_ENV.lock().unwrap();
+    let expr = parser.parse().map_err(|e| e.shifted(1))?;
 
-    let value = eval(&expr, &env)?;
+    let value = eval(&expr, env).map_err(|e| e.shifted(1))?;
 
-    env.set_var(name.clone(), value)?;
+    env.set_var(name.clone(), value).map_err(|e| e.at(0))?;
 
     Ok(format!("Variable '{}' set", name))
 }
 
-const RED: &str = "\x1b[31m";
-const RESET: &str = "\x1b[m";
-
-pub fn display_vars(tokens: Vec<(Token, String)>) {
-    tokens.iter().for_each(|tok| match tok.0 {
-        Token::Id => {
-            println!(
-                "\t- {} -> {:?}",
-                tok.1,
-                GLOBAL_ENV.lock().unwrap().get(&tok.1)
-            );
-        }
-        _ => {
-            eprintln!(
-                "{RED}\t- Error: `{}` was expected to be an identifier {RESET}",
-                tok.1
-            )
+/// Evaluates an expression against the environment.
+pub fn expr_eval(env: &Env, tokens: Vec<(Token, String)>) -> Result<Value, Error> {
+    let expr = Parser::new(tokens).parse()?;
+    eval(&expr, env)
+}
+
+/// `:assert <expr>`, failing unless `expr` is true (or non-zero). For a
+/// comparison the message shows both sides.
+pub fn assert_eval(env: &Env, tokens: Vec<(Token, String)>) -> Result<(), Error> {
+    if tokens.is_empty() {
+        return Err(Error::parse("Usage: :assert <expression>"));
+    }
+    let text = tokens
+        .iter()
+        .map(|(tok, t)| match tok {
+            Token::CString => format!("{t:?}"),
+            Token::CChar => format!("'{t}'"),
+            _ => t.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let expr = Parser::new(tokens).parse()?;
+    let holds = match eval(&expr, env)? {
+        Value::Bool(b) => b,
+        Value::Integer(i) => i != 0,
+        Value::Number(n) => n != 0.0,
+        Value::Pointer(p) => p != 0,
+        value => {
+            return Err(Error::eval(format!(
+                "`{text}` is {value:?}, not a condition"
+            )));
         }
+    };
+    if holds {
+        return Ok(());
+    }
+    match &expr {
+        Expr::Binary(left, _, right) => Err(Error::assertion(format!(
+            "`{text}` (left: {:?}, right: {:?})",
+            eval(left, env)?,
+            eval(right, env)?
+        ))),
+        _ => Err(Error::assertion(format!("`{text}`"))),
+    }
+}
+
+pub fn display_vars(env: &Env, tokens: Vec<(Token, String)>) -> Result<(), Error> {
+    if let Some(i) = tokens
+        .iter()
+        .position(|tok| !matches!(tok.0, Token::Id | Token::HistRef))
+    {
+        return Err(Error::parse(format!(
+            "`{}` was expected to be an identifier",
+            tokens[i].1
+        ))
+        .at(i));
+    }
+    tokens.iter().for_each(|tok| {
+        println!("\t- {} -> {:?}", tok.1, env.get(&tok.1));
     });
+    Ok(())
 }
 
-pub fn display_all() {
-    let env = GLOBAL_ENV.lock().unwrap();
+pub fn display_all(env: &Env) {
     let vars = env.vars.clone();
     let consts = env.consts.clone();
     println!("vars: ");
@@ -98,18 +139,57 @@ pub fn display_all() {
     for (name, value) in consts.iter() {
         println!("\t- {} -> {:?}", name, value)
     }
+
+    if !env.globals.is_empty() {
+        println!("\nglobals: ");
+    }
+    for (name, global) in env.globals.iter() {
+        println!(
+            "\t- {} ({} at {:#x}) -> {:?}",
+            name,
+            global.ty,
+            global.addr,
+            global.load()
+        )
+    }
 }
 
-#[inline(always)]
-pub fn get_value(var: &str) -> Option<Value> {
-    GLOBAL_ENV.lock().unwrap().get(var)
+/// Variables and results (as `$n`) holding an address inside one of `ranges`.
+pub fn vars_in_ranges(env: &Env, ranges: &[Range<usize>]) -> Vec<(String, usize)> {
+    let inside = |addr: usize| ranges.iter().any(|r| r.contains(&addr));
+    let address = |value: &Value| match value {
+        Value::Integer(addr) if inside(*addr as usize) => Some(*addr as usize),
+        Value::Pointer(addr) if inside(*addr) => Some(*addr),
+        _ => None,
+    };
+    let mut out: Vec<(String, usize)> = env
+        .vars
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), address(value)?)))
+        .collect();
+    out.sort();
+    out.extend(
+        env.history
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !entry.dropped)
+            .filter_map(|(i, entry)| Some((format!("${}", i + 1), address(&entry.value)?))),
+    );
+    out
 }
 
-#[inline(always)]
-pub fn set_value(var: &str, val: Value) {
-    GLOBAL_ENV
-        .lock()
-        .unwrap()
-        .set_var(var.to_string(), val)
-        .unwrap_or_else(|e| eprintln!("{e}"))
+pub fn display_history(env: &Env) {
+    for (i, entry) in env.history.iter().enumerate() {
+        if entry.dropped {
+            println!("\t${} dropped <- {}", i + 1, entry.call);
+            continue;
+        }
+        println!(
+            "\t${} = {} ({}) <- {}",
+            i + 1,
+            show_result(entry.ty, &entry.value),
+            entry.ty,
+            entry.call
+        );
+    }
 }
@@ -0,0 +1,80 @@
+use std::{
+    ffi::{CString, c_int},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+/// Non-blocking inotify instance, polled by the REPL between commands.
+pub struct Inotify {
+    fd: c_int,
+}
+
+impl Inotify {
+    pub fn new() -> Result<Self, String> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(format!(
+                "inotify_init1 failed: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(Self { fd })
+    }
+
+    /// Watches a directory rather than the file itself, so that rebuilds that
+    /// replace the file (new inode) are still noticed.
+    pub fn add_watch(&self, dir: &Path) -> Result<c_int, String> {
+        let c_dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| format!("Error {e}, Invalid directory {}", dir.display()))?;
+        let wd = unsafe {
+            libc::inotify_add_watch(
+                self.fd,
+                c_dir.as_ptr(),
+                libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+            )
+        };
+        if wd < 0 {
+            return Err(format!(
+                "Could not watch {}: {}",
+                dir.display(),
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(wd)
+    }
+
+    pub fn rm_watch(&self, wd: c_int) {
+        unsafe { libc::inotify_rm_watch(self.fd, wd) };
+    }
+
+    /// Drains the pending events, returning the watch descriptor and file name
+    /// of each.
+    pub fn read_events(&self) -> Vec<(c_int, String)> {
+        let mut events = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if n <= 0 {
+                break;
+            }
+            let mut offset = 0;
+            while offset < n as usize {
+                let event = unsafe {
+                    std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event)
+                };
+                let name_start = offset + size_of::<libc::inotify_event>();
+                let name = &buf[name_start..name_start + event.len as usize];
+                let name = name.split(|b| *b == 0).next().unwrap_or_default();
+                events.push((event.wd, String::from_utf8_lossy(name).to_string()));
+                offset = name_start + event.len as usize;
+            }
+        }
+        events
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}